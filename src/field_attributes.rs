@@ -1,7 +1,37 @@
-use proc_macro2::TokenStream;
-use proc_macro_error::emit_call_site_error;
+use std::collections::HashMap;
+use proc_macro2::{Span, TokenStream};
+use proc_macro_error::emit_error;
 use syn::{*, parse::{Parse, ParseStream}};
-use crate::markers::MarkerOutput;
+use crate::{markers::{MarkerOutput, get_marker_test}, utils::{check_regex, emit_meaningless_attribute_error, emit_missing_feature_error, emit_unknown_attribute_error, is_collection, is_option_of, is_type, skip_attribute_value}};
+
+const FIELD_ATTRIBUTE_NAMES : &[&str] = &[
+    "value", "regex", "prefix", "suffix", "brackets", "min", "sep", "separator", "optional", "cascade",
+    "followed_by", "not_followed_by", "exclude", "ignore",
+    "declare_marker", "set_marker", "unset_marker", "ignore_if_marker", "ignore_if_not_marker",
+    "consume_spaces", "consume_spaces_after_prefix", "consume_spaces_after_suffix", "consume_spaces_between_items",
+    "prefix_op", "infix_op", "postfix_op", "sync", "recover_until", "error", "list", "option",
+];
+
+const OPERATOR_ATTRIBUTE_NAMES : &[&str] = &["prefix_op", "infix_op", "postfix_op"];
+
+// Role of an enum variant in operator-precedence parsing
+#[derive(Clone, Copy, PartialEq)]
+pub enum OperatorKind {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+#[derive(Clone, Copy)]
+pub struct Operator {
+    pub kind: OperatorKind,
+    pub precedence: u32,
+    pub right_associative: bool,
+}
+
+const REPEATABLE_FIELD_ATTRIBUTE_NAMES : &[&str] = &[
+    "declare_marker", "set_marker", "unset_marker", "ignore_if_marker", "ignore_if_not_marker",
+];
 
 #[derive(Default)]
 pub struct FieldAttributes {
@@ -20,12 +50,19 @@ pub struct FieldAttributes {
     pub exclude: Option<String>,
     pub followed_by: Option<String>,
     pub not_followed_by: Option<String>,
+    pub sync: Option<String>,
+    pub recover_until: Option<String>,
+    pub error: Option<String>,
     pub declared_markers: Vec<LitStr>,
     pub set_markers: Vec<LitStr>,
     pub unset_markers: Vec<LitStr>,
     pub ignore_if_marker: Vec<LitStr>,
     pub ignore_if_not_marker: Vec<LitStr>,
     pub ignore: bool,
+    pub list: bool,
+    pub option: bool,
+    pub operator: Option<Operator>,
+    pub key_spans: HashMap<String, Span>,
 }
 
 impl Parse for FieldAttributes {
@@ -37,44 +74,79 @@ impl Parse for FieldAttributes {
         parenthesized!(content in input);
 
         while !content.is_empty() {
-            let name = content.parse::<Ident>()?.to_string();
+            let ident = content.parse::<Ident>()?;
+            let name = ident.to_string();
 
-            if name.as_str() == "ignore" {
-                attributes.ignore = true;
+            if !FIELD_ATTRIBUTE_NAMES.contains(&name.as_str()) {
+                emit_unknown_attribute_error(&ident, FIELD_ATTRIBUTE_NAMES);
+                skip_attribute_value(&content)?;
             } else {
-                content.parse::<Token![=]>()?;
-
-                match name.as_str() {
-                    "value" => attributes.value = Some(content.parse::<LitStr>()?.value()),
-                    "regex" => attributes.regex = Some(content.parse::<LitStr>()?.value()),
-                    "prefix" => attributes.prefix = Some(content.parse::<LitStr>()?.value()),
-                    "suffix" => attributes.suffix = Some(content.parse::<LitStr>()?.value()),
-                    "brackets" => {
-                        let brackets = content.parse::<LitStr>()?.value();
-
-                        if brackets.len() == 2 {
-                            attributes.prefix = Some((brackets.as_bytes()[0] as char).to_string());
-                            attributes.suffix = Some((brackets.as_bytes()[1] as char).to_string());
-                        }
-                    },
-                    "min" => attributes.min = Some(content.parse::<LitInt>()?.base10_parse::<usize>()?),
-                    "sep" => attributes.separator = Some(content.parse::<LitStr>()?.value()),
-                    "separator" => attributes.separator = Some(content.parse::<LitStr>()?.value()),
-                    "optional" => attributes.optional = Some(content.parse::<LitBool>()?.value()),
-                    "cascade" => attributes.cascade = Some(content.parse::<LitBool>()?.value()),
-                    "followed_by" => attributes.followed_by = Some(content.parse::<LitStr>()?.value()),
-                    "not_followed_by" => attributes.not_followed_by = Some(content.parse::<LitStr>()?.value()),
-                    "exclude" => attributes.exclude = Some(content.parse::<LitStr>()?.value()),
-                    "declare_marker" => attributes.declared_markers.push(content.parse::<LitStr>()?),
-                    "set_marker" => attributes.set_markers.push(content.parse::<LitStr>()?),
-                    "unset_marker" => attributes.unset_markers.push(content.parse::<LitStr>()?),
-                    "ignore_if_marker" => attributes.ignore_if_marker.push(content.parse::<LitStr>()?),
-                    "ignore_if_not_marker" => attributes.ignore_if_not_marker.push(content.parse::<LitStr>()?),
-                    "consume_spaces" => attributes.consume_spaces = Some(content.parse::<LitBool>()?.value()),
-                    "consume_spaces_after_prefix" => attributes.consume_spaces_after_prefix = Some(content.parse::<LitBool>()?.value()),
-                    "consume_spaces_after_suffix" => attributes.consume_spaces_after_suffix = Some(content.parse::<LitBool>()?.value()),
-                    "consume_spaces_between_items" => attributes.consume_spaces_between_items = Some(content.parse::<LitBool>()?.value()),
-                    _ => {}
+                let is_duplicate = attributes.key_spans.contains_key(&name)
+                    || (name == "sep" && attributes.key_spans.contains_key("separator"))
+                    || (name == "separator" && attributes.key_spans.contains_key("sep"))
+                    || (name == "brackets" && (attributes.key_spans.contains_key("prefix") || attributes.key_spans.contains_key("suffix")))
+                    || ((name == "prefix" || name == "suffix") && attributes.key_spans.contains_key("brackets"));
+
+                if is_duplicate && !REPEATABLE_FIELD_ATTRIBUTE_NAMES.contains(&name.as_str()) {
+                    emit_error!(ident, "duplicate attribute `{}`", name);
+                }
+
+                attributes.key_spans.insert(name.clone(), ident.span());
+
+                if OPERATOR_ATTRIBUTE_NAMES.contains(&name.as_str()) {
+                    if attributes.operator.is_some() {
+                        emit_error!(ident, "a variant can only be one kind of operator");
+                    }
+
+                    attributes.operator = Some(parse_operator(&ident, &content)?);
+                } else if name.as_str() == "ignore" {
+                    attributes.ignore = true;
+                } else if name.as_str() == "list" {
+                    attributes.list = true;
+                } else if name.as_str() == "option" {
+                    attributes.option = true;
+                } else {
+                    content.parse::<Token![=]>()?;
+
+                    match name.as_str() {
+                        "value" => attributes.value = Some(content.parse::<LitStr>()?.value()),
+                        "regex" => attributes.regex = Some(parse_regex(&content)?),
+                        "prefix" => attributes.prefix = Some(content.parse::<LitStr>()?.value()),
+                        "suffix" => attributes.suffix = Some(content.parse::<LitStr>()?.value()),
+                        "brackets" => {
+                            let lit_str = content.parse::<LitStr>()?;
+                            let brackets : Vec<char> = lit_str.value().chars().collect();
+
+                            match brackets.len() {
+                                2 => {
+                                    attributes.prefix = Some(brackets[0].to_string());
+                                    attributes.suffix = Some(brackets[1].to_string());
+                                },
+                                _ => emit_error!(lit_str, "`brackets` expects exactly two characters, e.g. \"()\"")
+                            }
+                        },
+                        "min" => attributes.min = Some(content.parse::<LitInt>()?.base10_parse::<usize>()?),
+                        "sep" => attributes.separator = Some(content.parse::<LitStr>()?.value()),
+                        "separator" => attributes.separator = Some(content.parse::<LitStr>()?.value()),
+                        "optional" => attributes.optional = Some(content.parse::<LitBool>()?.value()),
+                        "cascade" => attributes.cascade = Some(content.parse::<LitBool>()?.value()),
+                        "followed_by" => attributes.followed_by = Some(parse_regex(&content)?),
+                        "not_followed_by" => attributes.not_followed_by = Some(parse_regex(&content)?),
+                        "exclude" => attributes.exclude = Some(parse_regex(&content)?),
+                        "sync" => attributes.sync = Some(content.parse::<LitStr>()?.value()),
+                        "recover_until" => attributes.recover_until = Some(parse_regex(&content)?),
+                        "error" => attributes.error = Some(content.parse::<LitStr>()?.value()),
+                        "declare_marker" => attributes.declared_markers.push(content.parse::<LitStr>()?),
+                        "set_marker" => attributes.set_markers.push(content.parse::<LitStr>()?),
+                        "unset_marker" => attributes.unset_markers.push(content.parse::<LitStr>()?),
+                        "ignore_if_marker" => attributes.ignore_if_marker.push(content.parse::<LitStr>()?),
+                        "ignore_if_not_marker" => attributes.ignore_if_not_marker.push(content.parse::<LitStr>()?),
+                        "consume_spaces" => attributes.consume_spaces = Some(content.parse::<LitBool>()?.value()),
+                        "consume_spaces_after_prefix" => attributes.consume_spaces_after_prefix = Some(content.parse::<LitBool>()?.value()),
+                        "consume_spaces_after_suffix" => attributes.consume_spaces_after_suffix = Some(content.parse::<LitBool>()?.value()),
+                        "consume_spaces_between_items" => attributes.consume_spaces_between_items = Some(content.parse::<LitBool>()?.value()),
+                        _ => unreachable!()
+                    }
                 }
             }
 
@@ -83,10 +155,92 @@ impl Parse for FieldAttributes {
             }
         }
 
+        if let (Some(_), Some(span)) = (attributes.key_spans.get("sync"), attributes.key_spans.get("recover_until")) {
+            emit_error!(*span, "`recover_until` cannot be used along with `sync`");
+        }
+
+        if let (Some(_), Some(span)) = (attributes.key_spans.get("list"), attributes.key_spans.get("option")) {
+            emit_error!(*span, "`option` cannot be used along with `list`");
+        }
+
+        if !cfg!(feature = "error-messages") {
+            emit_missing_feature_error(&attributes.key_spans, &["error"], "error-messages", "`StringReader::set_expected_message`");
+        }
+
         Ok(attributes)
     }
 }
 
+// Parses the optional `(precedence = N, assoc = "left")` following an operator attribute.
+// Prefix and postfix operators bind tighter than every infix operator unless a precedence is given.
+fn parse_operator(ident: &Ident, content: ParseStream) -> syn::Result<Operator> {
+    let kind = match ident.to_string().as_str() {
+        "prefix_op" => OperatorKind::Prefix,
+        "infix_op" => OperatorKind::Infix,
+        _ => OperatorKind::Postfix,
+    };
+    let mut operator = Operator {
+        kind,
+        precedence: match kind {
+            OperatorKind::Infix => 0,
+            _ => u32::MAX,
+        },
+        right_associative: false,
+    };
+
+    if !content.peek(token::Paren) {
+        return Ok(operator);
+    }
+
+    let options;
+
+    parenthesized!(options in content);
+
+    while !options.is_empty() {
+        let option = options.parse::<Ident>()?;
+
+        options.parse::<Token![=]>()?;
+
+        match option.to_string().as_str() {
+            "precedence" => operator.precedence = options.parse::<LitInt>()?.base10_parse::<u32>()?,
+            "assoc" if kind == OperatorKind::Infix => {
+                let lit_str = options.parse::<LitStr>()?;
+
+                match lit_str.value().as_str() {
+                    "left" => operator.right_associative = false,
+                    "right" => operator.right_associative = true,
+                    other => emit_error!(lit_str, "unknown associativity `{}`", other; help = "expected \"left\" or \"right\""),
+                }
+            },
+            "assoc" => {
+                emit_error!(option, "`assoc` has no effect on `{}`", ident);
+                skip_attribute_value(&options)?;
+            },
+            _ => {
+                emit_unknown_attribute_error(&option, &["precedence", "assoc"]);
+                skip_attribute_value(&options)?;
+            }
+        }
+
+        if !options.is_empty() {
+            options.parse::<Token![,]>()?;
+        }
+    }
+
+    Ok(operator)
+}
+
+fn parse_regex(content: ParseStream) -> syn::Result<String> {
+    let lit_str = content.parse::<LitStr>()?;
+    let pattern = lit_str.value();
+
+    if let Err(message) = check_regex(&pattern) {
+        emit_error!(lit_str, "invalid regex: {}", message);
+    }
+
+    Ok(pattern)
+}
+
 impl FieldAttributes {
     pub fn from_field_attributes(attrs: &mut Vec<Attribute>) -> Self {
         let mut attributes = Self::default();
@@ -96,7 +250,7 @@ impl FieldAttributes {
 
             match result {
                 Ok(value) => attributes = value,
-                Err(error) => emit_call_site_error!(error)
+                Err(error) => emit_error!(error)
             };
 
             attrs.remove(i);
@@ -105,7 +259,62 @@ impl FieldAttributes {
         attributes
     }
 
+    pub fn check_field_type(&self, ty: &Type) {
+        let is_vec = self.is_list(ty);
+        let is_string = is_type(ty, "String") || is_option_of(ty, "String");
+
+        if !is_vec {
+            emit_meaningless_attribute_error(&self.key_spans, &["sep", "separator", "min", "consume_spaces_between_items"], "on a field that is not a `Vec`");
+        }
+
+        if !is_string {
+            emit_meaningless_attribute_error(&self.key_spans, &["regex", "value"], "on a field that is not a `String`");
+        }
+
+        emit_meaningless_attribute_error(&self.key_spans, OPERATOR_ATTRIBUTE_NAMES, "on a field");
+    }
+
+    // `list` and `option` tell the macro how to parse types it cannot recognize, such as aliases
+    pub fn is_list(&self, ty: &Type) -> bool {
+        self.list || is_collection(ty)
+    }
+
+    pub fn is_option(&self, ty: &Type) -> bool {
+        self.option || is_type(ty, "Option")
+    }
+
+    pub fn check_variant_fields(&self, fields: &Fields) {
+        emit_meaningless_attribute_error(&self.key_spans, &[
+            "value", "regex", "min", "optional", "cascade", "exclude", "followed_by", "not_followed_by", "ignore", "consume_spaces_between_items",
+            "sync", "recover_until", "list", "option",
+        ], "on an enum variant");
+
+        if self.operator.is_some() {
+            emit_meaningless_attribute_error(&self.key_spans, &["prefix", "suffix", "brackets", "sep", "separator"], "on an operator variant");
+        }
+
+        match fields {
+            Fields::Named(_) => {
+                emit_meaningless_attribute_error(&self.key_spans, &["sep", "separator"], "on a variant with named fields");
+            },
+            Fields::Unnamed(fields_unnamed) => {
+                if !fields_unnamed.unnamed.iter().all(|field| is_collection(&field.ty)) {
+                    emit_meaningless_attribute_error(&self.key_spans, &["sep", "separator"], "on a variant whose fields are not all `Vec`");
+                }
+            },
+            Fields::Unit => {
+                emit_meaningless_attribute_error(&self.key_spans, &[
+                    "prefix", "suffix", "brackets", "sep", "separator", "consume_spaces", "consume_spaces_after_prefix", "consume_spaces_after_suffix"
+                ], "on a variant with no field");
+            }
+        }
+    }
+
+    pub fn get_marker_test(&self) -> Option<TokenStream> {
+        get_marker_test(&self.ignore_if_marker, &self.ignore_if_not_marker)
+    }
+
     pub fn get_push_pop_markers(&self, field_index: usize) -> (TokenStream, TokenStream, TokenStream) {
-        MarkerOutput::from_attributes(&self.declared_markers, &self.set_markers, &self.unset_markers, Some(field_index)).to_tuple()
+        MarkerOutput::from_attributes(&self.declared_markers, &self.set_markers, &self.unset_markers, Some(field_index)).into_tuple()
     }
 }
\ No newline at end of file
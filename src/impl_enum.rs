@@ -1,49 +1,105 @@
-use proc_macro2::Span;
+use proc_macro2::{Span, TokenStream};
+use proc_macro_error::emit_error;
 use syn::*;
 use quote::quote;
-use crate::{field_attributes::FieldAttributes, output::Output, root_attributes::RootAttributes};
+use crate::{field_attributes::{FieldAttributes, OperatorKind}, impl_struct::{Expected, create_location_field, get_default_location, get_parse_expression, make_expectation, process_field}, output::Output, root_attributes::{Choice, RootAttributes}, source_writer::{FieldWriter, make_write_source, write_component, write_item_source}, utils::{check_type_support, emit_meaningless_attribute_error, is_boxed_self, is_located_type, make_warning}};
 
-pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes, output: &mut Output) {
+pub fn process_enum(name: &Ident, data_enum: &mut DataEnum, root_attributes: &RootAttributes, output: &mut Output) {
     let mut lines = vec![];
     let mut impl_display_lines = vec![];
     let mut get_location_lines = vec![];
     let mut completion_suggestions = vec![];
+    let mut write_source_lines = vec![];
+    let mut literals = vec![];
+    let mut variant_names = vec![];
+    let mut infix_lines = vec![];
+    let mut postfix_lines = vec![];
+    let mut has_operators = false;
     let has_name = root_attributes.name.is_some();
+    let item_name = root_attributes.get_item_name(name);
+    let default_location = get_default_location();
+    let declare_recovered = root_attributes.get_recovered_declaration();
 
-    for i in 0..data_enum.variants.len() {
-        let variant = &mut data_enum.variants[i];
+    emit_meaningless_attribute_error(&root_attributes.key_spans, &["value", "partial"], "on an enum");
 
-        // TODO: check if variant should be skipped to avoid recursion
+    // Only the fields of named-field variants are parsed one by one, the other variants fail as a whole
+    if !data_enum.variants.iter().any(|variant| matches!(variant.fields, Fields::Named(_))) {
+        emit_meaningless_attribute_error(&root_attributes.key_spans, &["sync", "recover_until"], "on an enum without named-field variants");
+    }
 
+    for i in 0..data_enum.variants.len() {
+        let variant = &mut data_enum.variants[i];
         let variant_name = &variant.ident;
-        let variant_name_as_str = variant_name.to_string();
         let attributes = FieldAttributes::from_field_attributes(&mut variant.attrs);
-        let mut parse_prefix = quote! { true };
-        let mut parse_suffix = quote! { true };
-        let mut parse_method = quote! { parse_item(reader__) };
-        let mut line = quote! { };
-        let mut pass_marker_test_fragments = vec![];
 
-        for marker_name in &attributes.ignore_if_marker {
-            pass_marker_test_fragments.push(quote! {
-                !reader__.get_marker(#marker_name)
-            });
+        attributes.check_variant_fields(&variant.fields);
+
+        // Without seed growing, a variant starting with the enum itself calls `parse_item` at the same index forever
+        let starts_with_self = attributes.prefix.is_none() && match variant.fields.iter().next() {
+            Some(first_field) => is_boxed_self(&first_field.ty, name) && !has_field_prefix(first_field),
+            None => false
+        };
+
+        // A marker condition can be what stops the recursion, e.g. a variant setting the marker it is ignored with
+        let has_marker_guard = !attributes.ignore_if_marker.is_empty() || !attributes.ignore_if_not_marker.is_empty()
+            || matches!(variant.fields.iter().next(), Some(first_field) if has_field_marker_guard(first_field));
+
+        if starts_with_self && !root_attributes.left_recursive && attributes.operator.is_none() && !has_marker_guard {
+            let first_field = variant.fields.iter().next().unwrap();
+
+            emit_error!(first_field.ty, "variant `{}` is left-recursive", variant_name; help = "add `left_recursive = true` to the root attributes");
         }
 
-        for marker_name in &attributes.ignore_if_not_marker {
-            pass_marker_test_fragments.push(quote! {
-                reader__.get_marker(#marker_name)
-            });
+        // Seed growing only extends the leftmost operand, the rightmost one is parsed from its own index and grows on its own
+        let ends_with_self = variant.fields.len() > 1 && is_boxed_self(&variant.fields.iter().last().unwrap().ty, name);
+
+        if starts_with_self && ends_with_self && root_attributes.left_recursive && attributes.operator.is_none() {
+            output.warnings.push(make_warning(variant_name.span(), format!(
+                "variant `{}` is right-associative with `left_recursive` (`a - b - c` is parsed as `a - (b - c)`), use `#[parsable(infix_op)]` to make it left-associative",
+                variant_name
+            )));
         }
 
-        let pass_marker_test = match pass_marker_test_fragments.is_empty() {
-            true => quote! { true },
-            false => quote! { #(#pass_marker_test_fragments)&&* }
-        };
+        let operator = attributes.operator.filter(|operator| {
+            let is_valid = is_operator_variant(&variant.fields, operator.kind, name);
+
+            if !is_valid {
+                let expected_fields = match operator.kind {
+                    OperatorKind::Prefix => "(Operator, Box<Self>)",
+                    OperatorKind::Infix => "(Box<Self>, Operator, Box<Self>)",
+                    OperatorKind::Postfix => "(Box<Self>, Operator)",
+                };
+
+                emit_error!(variant_name, "operator variant `{}` must have the fields `{}`", variant_name, expected_fields);
+            }
+
+            is_valid
+        });
+
+        has_operators |= operator.is_some();
+
+        let mut parse_prefix = quote! { true };
+        let mut parse_suffix = quote! { true };
+        let mut parse_method = quote! { parse_item(reader__) };
+        let mut line = quote! { };
+        let marker_test = attributes.get_marker_test();
+        let has_marker_test = marker_test.is_some();
+        let pass_marker_test = marker_test.unwrap_or_else(|| quote! { true });
 
         let (field_markers_on_start, field_markers_on_exit, field_markers_on_fail) = attributes.get_push_pop_markers(i);
+        let write_prefix = attributes.prefix.as_ref().map(|prefix| write_component(quote! { output__.push_str(#prefix); }, attributes.consume_spaces_after_prefix.unwrap_or(true)));
+        let write_suffix = attributes.suffix.as_ref().map(|suffix| write_component(quote! { output__.push_str(#suffix); }, attributes.consume_spaces_after_suffix.unwrap_or(true)));
+        let value_attributes = FieldAttributes {
+            separator: attributes.separator.clone(),
+            consume_spaces: attributes.consume_spaces,
+            ..FieldAttributes::default()
+        };
+
+        let variant_label = variant_name.to_string();
+        let expect_variant = make_expectation(Expected::Nothing, &attributes.error, &item_name, &variant_label, None);
 
         if let Some(prefix) = attributes.prefix {
+            let expect_prefix = make_expectation(Expected::String(prefix.clone()), &attributes.error, &item_name, &variant_label, Some("prefix"));
             let prefix_consume_spaces = match attributes.consume_spaces_after_prefix {
                 Some(false) => quote! { {} },
                 _ => quote! { reader__.eat_spaces() },
@@ -52,12 +108,16 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
             parse_prefix = quote! {
                 match reader__.read_string(#prefix) {
                     Some(_) => { #prefix_consume_spaces; true },
-                    None => { reader__.set_expected_string(#prefix); false }
+                    None => {
+                        #expect_prefix
+                        false
+                    }
                 }
             };
         }
 
         if let Some(suffix) = attributes.suffix {
+            let expect_suffix = make_expectation(Expected::String(suffix.clone()), &attributes.error, &item_name, &variant_label, Some("suffix"));
             let suffix_consume_spaces = match attributes.consume_spaces_after_suffix {
                 Some(false) => quote! { {} },
                 _ => quote! { reader__.eat_spaces() },
@@ -66,7 +126,10 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
             parse_suffix = quote! {
                 match reader__.read_string(#suffix) {
                     Some(_) => { #suffix_consume_spaces; true },
-                    None => { reader__.set_expected_string(#suffix); false }
+                    None => {
+                        #expect_suffix
+                        false
+                    }
                 }
             };
         }
@@ -75,23 +138,116 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
             parse_method = quote! { parse_item_with_separator(reader__, #separator) };
         }
 
-        match &variant.fields {
-            Fields::Named(_) => unreachable!(),
+        match &mut variant.fields {
+            Fields::Named(fields_named) => {
+                let mut markers_on_fail = vec![];
+                let mut field_names = vec![];
+                let mut field_lines = vec![];
+                let mut set_location = quote! {};
+                let mut write_lines = vec![];
+                let variant_item_name = format!("{}::{}", item_name, variant_name);
+
+                for (j, field) in fields_named.named.iter_mut().enumerate() {
+                    let field_attributes = FieldAttributes::from_field_attributes(&mut field.attrs);
+                    let field_name = field.ident.clone().unwrap();
+
+                    write_lines.push(FieldWriter::from_attributes(&field_attributes, &field.ty).to_tokens(&quote! { #field_name }, &write_item_source));
+                    field_lines.push(process_field(field, field_attributes, j, root_attributes, &variant_item_name, &mut markers_on_fail, &quote! {
+                        reader__.set_index(start_index__);
+                        return None;
+                    }));
+                    field_names.push(field_name);
+                }
+
+                write_source_lines.push(quote! {
+                    Self::#variant_name { #(#field_names,)* .. } => {
+                        #write_prefix
+                        #(#write_lines)*
+                        #write_suffix
+                    },
+                });
+
+                if root_attributes.located {
+                    let mut location_field = create_location_field(Some("location"));
+
+                    location_field.vis = Visibility::Inherited;
+                    fields_named.named.insert(0, location_field);
+                    field_names.push(Ident::new("location", Span::call_site()));
+                    set_location = quote! { let location = reader__.get_item_location(start_index__); };
+
+                    get_location_lines.push(quote! {
+                        Self::#variant_name { location, .. } => location,
+                    });
+                } else {
+                    get_location_lines.push(quote! {
+                        Self::#variant_name { .. } => #default_location,
+                    });
+                }
+
+                // The fields are parsed in a closure so that a failing field only discards this variant
+                line = quote! {
+                    let prefix_ok__ = #parse_prefix;
+
+                    if prefix_ok__ {
+                        let variant__ = (|reader__: &mut parsable::StringReader| -> Option<Self> {
+                            let mut field_index__ : usize = 0;
+                            let mut field_failed__ = false;
+                            let mut prefix_ok__ = true;
+                            let mut option_failed__ = false;
+                            #declare_recovered
+                            #(#field_lines)*
+                            #set_location
+                            Some(Self::#variant_name { #(#field_names),* })
+                        })(reader__);
+
+                        if let Some(value__) = variant__ {
+                            let suffix_ok__ = #parse_suffix;
+
+                            if suffix_ok__ {
+                                #field_markers_on_exit
+                                return Some(value__);
+                            }
+                        }
+
+                        #expect_variant
+                    }
+
+                    reader__.set_index(start_index__);
+                };
+            },
             Fields::Unnamed(fields_unnamed) => {
                 let mut value_names = vec![];
+                let mut write_lines = vec![];
 
-                for i in 0..fields_unnamed.unnamed.len() {
+                for (i, field) in fields_unnamed.unnamed.iter().enumerate() {
                     let value_name = Ident::new(&format!("value_{}", i), Span::call_site());
 
+                    write_lines.push(FieldWriter::from_attributes(&value_attributes, &field.ty).to_tokens(&quote! { #value_name }, &write_item_source));
                     value_names.push(quote! { #value_name });
                 }
 
+                write_source_lines.push(quote! {
+                    Self::#variant_name(#(#value_names,)* ..) => {
+                        #write_prefix
+                        #(#write_lines)*
+                        #write_suffix
+                    },
+                });
+
+                // Variants with a single item field use the location of that field, the others store their own if asked to
+                let uses_field_location = fields_unnamed.unnamed.len() == 1 && is_located_type(&fields_unnamed.unnamed[0].ty);
+                let stores_location = root_attributes.stores_variant_locations() && !uses_field_location;
+                let make_variant = match stores_location {
+                    true => quote! { Self::#variant_name(#(#value_names,)* reader__.get_item_location(start_index__)) },
+                    false => quote! { Self::#variant_name(#(#value_names),*) }
+                };
+
                 let mut current_block_single = quote! {
                     let suffix_ok__ = #parse_suffix;
 
                     if suffix_ok__ {
                         #field_markers_on_exit
-                        return Some(Self::#variant_name(#(#value_names),*))
+                        return Some(#make_variant)
                     }
                 };
 
@@ -103,10 +259,15 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
                         _ => quote! { reader__.eat_spaces(); },
                     };
 
+                    let parse_expression = match check_type_support(field_type) {
+                        true => get_parse_expression(field_type, &parse_method, &consume_spaces),
+                        false => quote! { Option::<#field_type>::None },
+                    };
+
                     value_names.insert(0, quote! { #value_name });
 
                     current_block_single = quote! {
-                        if let Some(#value_name) = <#field_type as parsable::Parsable>::#parse_method {
+                        if let Some(#value_name) = #parse_expression {
                             #consume_spaces
                             #current_block_single
                         }
@@ -118,26 +279,101 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
 
                     if prefix_ok__ {
                         #current_block_single
+                        #expect_variant
                     }
 
                     reader__.set_index(start_index__);
                 };
 
-                if fields_unnamed.unnamed.len() == 1 {
+                // Operator variants bind the operator and the boxed operands to the `value_i` names used by `make_variant`
+                if let Some(operator) = operator {
+                    let operator_type = match operator.kind {
+                        OperatorKind::Prefix => &fields_unnamed.unnamed[0].ty,
+                        _ => &fields_unnamed.unnamed[1].ty,
+                    };
+                    let consume_spaces = match attributes.consume_spaces {
+                        Some(false) => quote! { },
+                        _ => quote! { reader__.eat_spaces(); },
+                    };
+                    let precedence = operator.precedence;
+
+                    line = match operator.kind {
+                        OperatorKind::Prefix => quote! {
+                            if let Some(value_0) = <#operator_type as parsable::Parsable>::parse_item(reader__) {
+                                #consume_spaces
+
+                                if let Some(operand__) = Self::parse_with_precedence__(reader__, #precedence) {
+                                    let value_1 = Box::new(operand__);
+
+                                    #field_markers_on_exit
+                                    return Some(#make_variant);
+                                }
+                            }
+
+                            reader__.set_index(start_index__);
+                        },
+                        OperatorKind::Infix => {
+                            let rhs_precedence = match operator.right_associative {
+                                true => precedence,
+                                false => precedence.saturating_add(1),
+                            };
+
+                            quote! {
+                                if #precedence >= min_precedence__ {
+                                    if let Some(value_1) = <#operator_type as parsable::Parsable>::parse_item(reader__) {
+                                        #consume_spaces
+
+                                        if let Some(rhs__) = Self::parse_with_precedence__(reader__, #rhs_precedence) {
+                                            let value_0 = Box::new(lhs__);
+                                            let value_2 = Box::new(rhs__);
+
+                                            lhs__ = #make_variant;
+                                            #field_markers_on_exit
+                                            continue;
+                                        }
+                                    }
+
+                                    reader__.set_index(operator_index__);
+                                }
+                            }
+                        },
+                        OperatorKind::Postfix => quote! {
+                            if #precedence >= min_precedence__ {
+                                if let Some(value_1) = <#operator_type as parsable::Parsable>::parse_item(reader__) {
+                                    let value_0 = Box::new(lhs__);
+
+                                    #consume_spaces
+                                    lhs__ = #make_variant;
+                                    #field_markers_on_exit
+                                    continue;
+                                }
+
+                                reader__.set_index(operator_index__);
+                            }
+                        },
+                    };
+                }
+
+                if uses_field_location {
                     let field = &fields_unnamed.unnamed[0];
                     let field_type = &field.ty;
 
                     get_location_lines.push(quote! {
                         Self::#variant_name(value) => <#field_type as parsable::Parsable>::location(value),
                     });
+                } else if stores_location {
+                    let mut location_field = create_location_field(None);
+
+                    location_field.vis = Visibility::Inherited;
+                    fields_unnamed.unnamed.push(location_field);
+
+                    get_location_lines.push(quote! {
+                        Self::#variant_name(.., location) => location,
+                    });
                 } else {
-                    let mut fields = vec![];
-                    
-                    for _ in 0..fields_unnamed.unnamed.len() {
-                        fields.push(quote! { _ });
-                    }
+                    // Without `located_variants`, the variant keeps its shape and reports the empty location
                     get_location_lines.push(quote! {
-                        Self::#variant_name(#(#fields),*) => panic!("variant `{}` has no location (because it doesn't have exactly 1 field)", #variant_name_as_str),
+                        Self::#variant_name(..) => #default_location,
                     });
                 }
             },
@@ -154,43 +390,93 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
                     _ => None
                 };
 
-                get_location_lines.push(quote! {
-                    Self::#variant_name => panic!("variant `{}` has no location (because it doesn't have exactly 1 field)", #variant_name_as_str),
-                });
+                // With `located_variants`, unit variants become `Variant(ItemLocation)` so that they can report where they were parsed
+                let make_variant = match root_attributes.stores_variant_locations() {
+                    true => {
+                        get_location_lines.push(quote! {
+                            Self::#variant_name(location) => location,
+                        });
+
+                        quote! { Self::#variant_name(reader__.get_item_location(start_index__)) }
+                    },
+                    false => {
+                        get_location_lines.push(quote! {
+                            Self::#variant_name => #default_location,
+                        });
+
+                        quote! { Self::#variant_name }
+                    }
+                };
 
                 match string {
                     Some(lit_str) => {
+                        if !has_marker_test {
+                            literals.push(lit_str.clone());
+                        }
+
+                        // A custom error message replaces the literal, even when the enum has a name
+                        let expect_literal = match &attributes.error {
+                            Some(_) => quote! { else { #expect_variant } },
+                            None => quote! {
+                                else if (! #has_name) {
+
+                                    reader__.set_expected_string(#lit_str);
+                                }
+                            },
+                        };
+
                         completion_suggestions.push(lit_str.clone());
                         line = quote! {
                             if let Some(_) = reader__.read_string(#lit_str) {
+                                let variant__ = #make_variant;
+
                                 reader__.eat_spaces();
                                 #field_markers_on_exit
-                                return Some(Self::#variant_name);
-                            } else if (! #has_name) {
-
-                                reader__.set_expected_string(#lit_str);
-                            }
+                                return Some(variant__);
+                            } #expect_literal
                         };
 
                         impl_display_lines.push(quote! {
-                            Self::#variant_name => #lit_str,
+                            Self::#variant_name { .. } => #lit_str,
+                        });
+
+                        write_source_lines.push(quote! {
+                            Self::#variant_name { .. } => output__.push_str(#lit_str),
                         });
                     },
                     None => {
+                        write_source_lines.push(quote! {
+                            Self::#variant_name { .. } => {},
+                        });
+
                         // emit_call_site_error!("variants with no field must have an associated string literal")
                     }
                 }
             }
         }
 
-        lines.push(quote! {
+        if root_attributes.stores_variant_locations() && matches!(variant.fields, Fields::Unit) {
+            variant.fields = Fields::Unnamed(syn::parse_str("(parsable::ItemLocation)").unwrap());
+        }
+
+        let line = quote! {
             if (#pass_marker_test) {
                 #field_markers_on_start
                 #line
                 #field_markers_on_fail
                 #field_markers_on_exit
             }
-        });
+        };
+
+        // Infix and postfix operators extend an already parsed operand, the other variants are operands themselves
+        match operator.map(|operator| operator.kind) {
+            Some(OperatorKind::Infix) => infix_lines.push(line),
+            Some(OperatorKind::Postfix) => postfix_lines.push(line),
+            _ => {
+                lines.push(line);
+                variant_names.push(variant.ident.to_string());
+            }
+        }
     }
 
     for variant in data_enum.variants.iter_mut() {
@@ -219,15 +505,165 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
         }
     });
 
-    output.parse_item = quote! {
-        fn parse_item(reader__: &mut parsable::StringReader) -> Option<Self> {
+    // A memoized enum records the variant that matched, so that parsing it again at the same index only tries this one
+    let has_choices = root_attributes.is_memoized() && !root_attributes.left_recursive && !has_operators;
+    let variant_indices = 0..lines.len();
+    let variant_guards : Vec<TokenStream> = variant_indices.clone().map(|i| match has_choices {
+        true => quote! { choice__.map_or(true, |choice__| choice__ == #i) },
+        false => quote! { true },
+    }).collect();
+    let set_chosen = |index: TokenStream| match has_choices {
+        true => quote! { chosen__ = #index; },
+        false => quote! {},
+    };
+    let root_marker_check = root_attributes.get_marker_check();
+    let parse_variants = match root_attributes.choice {
+        Choice::First => {
+            for (i, literal) in literals.iter().enumerate() {
+                if let Some(previous) = literals[..i].iter().find(|previous| literal.value().starts_with(&previous.value())) {
+                    output.warnings.push(make_warning(literal.span(), format!(
+                        "\"{}\" can never be matched because the previous variant \"{}\" matches first, reorder the variants or use `choice = \"longest\"`",
+                        literal.value(),
+                        previous.value()
+                    )));
+                }
+            }
+
+            let set_chosen = variant_indices.map(|i| set_chosen(quote! { #i }));
+
+            quote! {
+                #(
+                    if #variant_guards {
+                        #set_chosen
+                        #lines
+                    }
+                )*
+
+                None
+            }
+        },
+        Choice::Longest => {
+            let set_chosen = set_chosen(quote! { variant_index__ });
+
+            quote! {
+                let mut longest__ : Option<(usize, usize, Self)> = None;
+
+                #(
+                    reader__.set_index(start_index__);
+
+                    if #variant_guards {
+                        if let Some(value__) = (|reader__: &mut parsable::StringReader| -> Option<Self> { #lines None })(reader__) {
+                            let end_index__ = reader__.get_index();
+                            let is_longest__ = match &longest__ {
+                                Some((_, longest_end_index__, _)) => end_index__ > *longest_end_index__,
+                                None => true
+                            };
+
+                            if is_longest__ {
+                                longest__ = Some((#variant_indices, end_index__, value__));
+                            }
+                        }
+                    }
+                )*
+
+                match longest__ {
+                    Some((variant_index__, end_index__, value__)) => {
+                        #set_chosen
+                        reader__.set_index(end_index__);
+                        Some(value__)
+                    },
+                    None => {
+                        reader__.set_index(start_index__);
+                        None
+                    }
+                }
+            }
+        },
+        // Every variant is tried, the reader records an ambiguity if more than one of them matches and the first one is kept
+        Choice::Strict => {
+            let set_chosen = set_chosen(quote! { variant_index__ });
+
+            quote! {
+                let mut matches__ : Vec<(&'static str, usize, usize, Self)> = vec![];
+
+                #(
+                    reader__.set_index(start_index__);
+
+                    if #variant_guards {
+                        if let Some(value__) = (|reader__: &mut parsable::StringReader| -> Option<Self> { #lines None })(reader__) {
+                            matches__.push((#variant_names, #variant_indices, reader__.get_index(), value__));
+                        }
+                    }
+                )*
+
+                if matches__.len() > 1 {
+                    let variant_names__ : Vec<&'static str> = matches__.iter().map(|(variant_name__, _, _, _)| *variant_name__).collect();
+
+                    reader__.set_index(matches__[0].2);
+                    let location__ = reader__.get_item_location(start_index__);
+                    reader__.add_ambiguity(#item_name, &variant_names__, location__);
+                }
+
+                match matches__.into_iter().next() {
+                    Some((_, variant_index__, end_index__, value__)) => {
+                        #set_chosen
+                        reader__.set_index(end_index__);
+                        Some(value__)
+                    },
+                    None => {
+                        reader__.set_index(start_index__);
+                        None
+                    }
+                }
+            }
+        }
+    };
+
+    let parse_body = match has_operators {
+        true => {
+            // Precedence climbing: an operand is parsed first, then operators binding at least as tightly as `min_precedence__` extend it
+            output.parse_with_precedence = Some(quote! {
+                #[doc(hidden)]
+                pub fn parse_with_precedence__(reader__: &mut parsable::StringReader, min_precedence__: u32) -> Option<Self> {
+                    let start_index__ = reader__.get_index();
+                    let mut lhs__ = (|reader__: &mut parsable::StringReader| -> Option<Self> { #parse_variants })(reader__)?;
+
+                    loop {
+                        let operator_index__ = reader__.get_index();
+
+                        #(#postfix_lines)*
+                        #(#infix_lines)*
+
+                        break;
+                    }
+
+                    Some(lhs__)
+                }
+            });
+
+            root_attributes.get_memoized_parse_item(quote! {
+                Self::parse_with_precedence__(reader__, 0)
+            }, false)
+        },
+        false => root_attributes.get_memoized_parse_item(quote! {
             let start_index__ = reader__.get_index();
-            #(#lines)*
+            #parse_variants
+        }, has_choices)
+    };
 
-            None
+    output.parse_item = quote! {
+        fn parse_item(reader__: &mut parsable::StringReader) -> Option<Self> {
+            #root_marker_check
+            #parse_body
         }
     };
 
+    output.write_source = make_write_source(quote! {
+        match self {
+            #(#write_source_lines)*
+        }
+    });
+
     output.get_location = quote! {
         fn location(&self) -> &parsable::ItemLocation {
             match self {
@@ -241,4 +677,39 @@ pub fn process_enum(data_enum: &mut DataEnum, root_attributes: &RootAttributes,
             &[ #(#completion_suggestions),* ]
         }
     });
-}
\ No newline at end of file
+}
+
+fn is_operator_variant(fields: &Fields, kind: OperatorKind, name: &Ident) -> bool {
+    let field_types : Vec<&Type> = match fields {
+        Fields::Unnamed(fields_unnamed) => fields_unnamed.unnamed.iter().map(|field| &field.ty).collect(),
+        _ => return false
+    };
+
+    match (kind, field_types.as_slice()) {
+        (OperatorKind::Prefix, [_, operand]) => is_boxed_self(operand, name),
+        (OperatorKind::Infix, [lhs, _, rhs]) => is_boxed_self(lhs, name) && is_boxed_self(rhs, name),
+        (OperatorKind::Postfix, [operand, _]) => is_boxed_self(operand, name),
+        _ => false
+    }
+}
+
+// Looks for a `prefix` (or `ignore`) key in the field attributes without parsing them, they are parsed later along with the field
+fn has_field_prefix(field: &Field) -> bool {
+    field.attrs.iter()
+        .filter(|attr| attr.path.segments.last().unwrap().ident == "parsable")
+        .any(|attr| contains_ident(attr.tokens.clone(), &["prefix", "brackets", "ignore"]))
+}
+
+fn has_field_marker_guard(field: &Field) -> bool {
+    field.attrs.iter()
+        .filter(|attr| attr.path.segments.last().unwrap().ident == "parsable")
+        .any(|attr| contains_ident(attr.tokens.clone(), &["ignore_if_marker", "ignore_if_not_marker"]))
+}
+
+fn contains_ident(tokens: proc_macro2::TokenStream, names: &[&str]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        proc_macro2::TokenTree::Ident(ident) => names.iter().any(|name| ident == name),
+        proc_macro2::TokenTree::Group(group) => contains_ident(group.stream(), names),
+        _ => false
+    })
+}
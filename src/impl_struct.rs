@@ -1,6 +1,8 @@
+use proc_macro2::TokenStream;
+use proc_macro_error::{emit_call_site_error, emit_error};
 use syn::{*, parse::{Parse, ParseStream}};
-use quote::quote;
-use crate::{field_attributes::FieldAttributes, output::Output, root_attributes::RootAttributes, utils::is_type};
+use quote::{format_ident, quote};
+use crate::{field_attributes::FieldAttributes, output::Output, root_attributes::RootAttributes, source_writer::{FieldWriter, make_display, make_write_source, write_item_display, write_item_source}, utils::{check_type_support, emit_meaningless_attribute_error, get_collection_item_type, get_inner_type, get_recovered_type, is_collection, is_option_of, is_type, make_ident}};
 
 struct Wrapper {
     field: Field
@@ -14,309 +16,847 @@ impl Parse for Wrapper {
     }
 }
 
-pub fn create_location_field(field_name: &str) -> Field {
-    let string = format!("pub {}: parsable::ItemLocation", field_name);
+pub fn create_location_field(field_name: Option<&str>) -> Field {
+    let string = format!("pub {}: parsable::ItemLocation", field_name.unwrap_or("location"));
     let result : Result<Wrapper> = syn::parse_str(&string);
+    let mut field = result.unwrap().field;
 
-    result.unwrap().field
+    if field_name.is_none() {
+        field.ident = None;
+        field.colon_token = None;
+    }
+
+    field
 }
 
-pub fn process_struct(data_struct: &mut DataStruct, root_attributes: &mut RootAttributes, output: &mut Output) {
-    output.get_location = quote! {
-        fn location(&self) -> &parsable::ItemLocation {
-            &self.location
-        }
-    };
+// Items that are not located don't store an `ItemLocation`, they all share the same empty one. It is provided by the runtimes that enable
+// the `empty-location` feature, otherwise each thread allocates its own once, since `ItemLocation` may not be shareable across threads.
+pub fn get_default_location() -> TokenStream {
+    match cfg!(feature = "empty-location") {
+        true => quote! { parsable::ItemLocation::empty() },
+        false => quote! {
+            {
+                thread_local! {
+                    static DEFAULT_LOCATION__: &'static parsable::ItemLocation = Box::leak(Box::new(<parsable::ItemLocation as Default>::default()));
+                }
+
+                DEFAULT_LOCATION__.with(|location| *location)
+            }
+        },
+    }
+}
 
+pub fn process_struct(name: &Ident, data_struct: &mut DataStruct, root_attributes: &mut RootAttributes, output: &mut Output) {
     let (root_markers_on_start, root_markers_on_exit, root_markers_on_fail) = root_attributes.get_push_pop_markers();
     let mut markers_on_fail = vec![root_markers_on_fail];
+    let field_count = data_struct.fields.len();
+    let mut field_names = vec![];
+    let mut lines = vec![];
+    let mut write_lines = vec![];
+    let mut display_lines = vec![];
+    let is_partial = root_attributes.partial && matches!(data_struct.fields, Fields::Named(_));
+    let partial_name = format_ident!("Partial{}", name);
+    let all_field_names : Vec<Option<Ident>> = data_struct.fields.iter().map(|field| field.ident.clone()).collect();
+    let mut partial_fields = vec![];
+
+    if !is_partial {
+        emit_meaningless_attribute_error(&root_attributes.key_spans, &["partial"], "on a struct without named fields");
+    }
+
+    for (i, field) in data_struct.fields.iter_mut().enumerate() {
+        let field_name = get_field_name(field, i);
+
+        let mut attributes = FieldAttributes::from_field_attributes(&mut field.attrs);
+
+        if field_count == 1 && attributes.regex.is_none() {
+            // A single string field either gives its literal to the item, or matches the literal given by `token`
+            match (&root_attributes.token, &attributes.value) {
+                (Some(token), None) if is_type(&field.ty, "String") || is_option_of(&field.ty, "String") => attributes.value = Some(token.clone()),
+                (None, Some(value)) => root_attributes.token = Some(value.clone()),
+                _ => {}
+            }
+        }
+
+        // The single field may have just given its literal to the item, which then names it
+        let item_name = root_attributes.get_item_name(name);
+        let field_access = match &field.ident {
+            Some(ident) => quote! { &self.#ident },
+            None => {
+                let index = Index::from(i);
+                quote! { &self.#index }
+            }
+        };
+
+        let field_writer = FieldWriter::from_attributes(&attributes, &field.ty);
+
+        // A partial item holds the fields parsed before the one that failed
+        let exit_on_fail = match is_partial {
+            true => {
+                let parsed_field_names = &all_field_names[..i];
+                let remaining_field_names = &all_field_names[i..];
+
+                quote! {
+                    let failure_location__ = reader__.get_item_location(field_index__);
 
-    match &mut data_struct.fields {
-        Fields::Named(named_fields) => {
-            let field_count = named_fields.named.len();
-            let mut field_names = vec![];
-            let mut lines = vec![];
-
-            for (i, field) in named_fields.named.iter_mut().enumerate() {
-                let attributes = FieldAttributes::from_field_attributes(&mut field.attrs);
-                let (field_markers_on_start, field_markers_on_exit, field_markers_on_fail) = attributes.get_push_pop_markers(i);
-                let is_vec = is_type(&field.ty, "Vec");
-                let is_option = is_type(&field.ty, "Option");
-
-                let field_name = field.ident.as_ref().unwrap();
-                let field_type = &field.ty;
-
-                field_names.push(quote! { #field_name });
-                markers_on_fail.insert(0, field_markers_on_fail);
-
-                let optional = is_option || attributes.optional.unwrap_or(false);
-                let participate_in_cascade = root_attributes.cascade && attributes.cascade.unwrap_or(true);
-                let consume_spaces = match attributes.consume_spaces {
-                    Some(false) => quote! {},
-                    _ => quote! { reader__.eat_spaces(); }
-                };
-                let mut handle_failure = quote! {};
-                let mut on_fail = quote ! {
                     reader__.set_index(start_index__);
-                    #(#markers_on_fail)*
                     #root_markers_on_exit
-                    return None;
-                };
+                    return Err(#partial_name {
+                        #(#parsed_field_names: Some(#parsed_field_names),)*
+                        #(#remaining_field_names: None,)*
+                        failure_location: failure_location__
+                    });
+                }
+            },
+            false => quote! {
+                reader__.set_index(start_index__);
+                #root_markers_on_exit
+                return None;
+            }
+        };
 
-                if optional {
-                    let set_option_failed = match participate_in_cascade {
-                        true => quote! { option_failed__ = true },
-                        false => quote! {},
-                    };
+        if is_partial {
+            let field_visibility = &field.vis;
+            let field_type = &field.ty;
 
-                    on_fail = quote! {
-                        field_failed__ = true;
-                        #set_option_failed;
-                        reader__.set_index(field_index__);
-                        <#field_type as Default>::default()
-                    };
+            partial_fields.push(quote! { #field_visibility #field_name: Option<#field_type> });
+        }
 
-                    if attributes.suffix.is_some() {
-                        handle_failure = quote! {
-                            if field_failed__ {
-                                #field_name = <#field_type as Default>::default();
-                            }
-                        }
-                    }
-                }
+        write_lines.push(field_writer.to_tokens(&field_access, &write_item_source));
+        display_lines.push(field_writer.to_tokens(&field_access, &write_item_display));
+        lines.push(process_field(field, attributes, i, root_attributes, &item_name, &mut markers_on_fail, &exit_on_fail));
+        field_names.push(field_name);
+    }
 
-                let mut check = vec![];
-                let has_prefix = attributes.prefix.is_some();
-                let has_suffix = attributes.suffix.is_some();
+    if !matches!(data_struct.fields, Fields::Unit) {
+        emit_meaningless_attribute_error(&root_attributes.key_spans, &["value"], "on a struct with fields");
+    }
 
-                let mut pre_parsing_check = quote! {};
+    emit_meaningless_attribute_error(&root_attributes.key_spans, &["choice", "located_variants"], "on a struct");
 
-                if optional && participate_in_cascade {
-                    pre_parsing_check = quote! {
-                        if option_failed__ {
-                            field_failed__ = true;
+    if let Fields::Unit = data_struct.fields {
+        match root_attributes.value.clone().or_else(|| root_attributes.token.clone()) {
+            Some(literal) => {
+                lines.push(quote! {
+                    match reader__.read_string(#literal) {
+                        Some(_) => reader__.eat_spaces(),
+                        None => {
+                            reader__.set_index(start_index__);
+                            #(#markers_on_fail)*
+                            #root_markers_on_exit
+                            return None;
                         }
                     };
+                });
+
+                write_lines.push(quote! {
+                    output__.push_str(#literal);
+                });
+                display_lines.push(quote! {
+                    output__.push_str(#literal);
+                });
+
+                root_attributes.token = Some(literal);
+            },
+            None => emit_call_site_error!("unit structs must specify the literal they match with `token = \"...\"` or `value = \"...\"`")
+        }
+    }
+
+    let mut set_location = quote! {};
+
+    if root_attributes.located {
+        set_location = quote! { let location = reader__.get_item_location(start_index__); };
+        field_names.push(make_ident("location".to_string()));
+    }
+
+    let make_self = match &mut data_struct.fields {
+        Fields::Named(named_fields) => {
+            if root_attributes.located {
+                named_fields.named.insert(0, create_location_field(Some("location")));
+            }
+
+            output.get_location = quote! {
+                fn location(&self) -> &parsable::ItemLocation {
+                    &self.location
                 }
+            };
 
-                let prefix_parsing = match attributes.prefix {
-                    Some(prefix) => {
-                        let prefix_consume_spaces = match attributes.consume_spaces_after_prefix {
-                            Some(false) => quote! { {} },
-                            _ => quote! { reader__.eat_spaces() },
-                        };
-
-                        quote! {
-                            if !field_failed__ {
-                                match reader__.read_string(#prefix) {
-                                    Some(_) => #prefix_consume_spaces,
-                                    None => {
-                                        reader__.set_expected_string(#prefix);
-                                        prefix_ok__ = false;
-                                        field_failed__ = true;
-                                        #on_fail;
-                                    }
-                                };
-                            }
-                        }
-                    },
-                    None => quote! {}
-                };
-                let suffix_parsing = match attributes.suffix {
-                    Some(suffix) => {
-                        let suffix_consume_spaces = match attributes.consume_spaces_after_suffix {
-                            Some(false) => quote! { {} },
-                            _ => quote! { reader__.eat_spaces() },
-                        };
-
-                        quote! {
-                            if !field_failed__ {
-                                match reader__.read_string(#suffix) {
-                                    Some(_) => #suffix_consume_spaces,
-                                    None => {
-                                        reader__.set_expected_string(#suffix);
-                                        #on_fail;
-                                    }
-                                };
-                            }
-                        }
-                    },
-                    None => quote! {}
-                };
+            quote! { Self { #(#field_names),* } }
+        },
+        Fields::Unnamed(unnamed_fields) => {
+            // The location is appended after the positional fields so that `self.0`, `self.1`... keep their meaning
+            let location_index = Index::from(unnamed_fields.unnamed.len());
 
-                let mut exclude_parsing = quote! {};
+            if root_attributes.located {
+                unnamed_fields.unnamed.push(create_location_field(None));
+            }
 
-                if let Some(exclude) = &attributes.exclude {
-                    exclude_parsing = quote! {
-                        if !field_failed__ && reader__.peek_regex(#exclude) {
-                            #on_fail;
-                        }
-                    };
+            output.get_location = quote! {
+                fn location(&self) -> &parsable::ItemLocation {
+                    &self.#location_index
                 }
+            };
 
-                let mut followed_by_parsing = quote! {};
+            quote! { Self(#(#field_names),*) }
+        },
+        Fields::Unit => {
+            if root_attributes.located {
+                // A unit struct has nowhere to store its location, so it becomes `struct Name { location }`
+                let mut named_fields : FieldsNamed = syn::parse_str("{}").unwrap();
 
-                if let Some(followed_by) = &attributes.followed_by {
-                    followed_by_parsing = quote! {
-                        if !field_failed__ && !reader__.peek_regex(#followed_by) {
-                            reader__.set_expected_regex(#followed_by);
-                            #on_fail;
-                        }
-                    };
-                } else if let Some(not_followed_by) = &attributes.not_followed_by {
-                    followed_by_parsing = quote! {
-                        if !field_failed__ && reader__.peek_regex(#not_followed_by) {
-                            // reader__.set_expected_regex(#not_followed_by);
-                            #on_fail;
-                        }
-                    };
+                named_fields.named.push(create_location_field(Some("location")));
+                data_struct.fields = Fields::Named(named_fields);
+                data_struct.semi_token = None;
+            }
+
+            output.get_location = quote! {
+                fn location(&self) -> &parsable::ItemLocation {
+                    &self.location
                 }
+            };
+
+            match root_attributes.located {
+                true => quote! { Self { #(#field_names),* } },
+                false => quote! { Self }
+            }
+        }
+    };
 
-                let mut parse_method = quote! { parse_item(reader__) };
+    if !root_attributes.located {
+        let default_location = get_default_location();
 
-                if is_vec {
-                    if let Some(separator) = attributes.separator {
-                        parse_method = quote! { parse_item_with_separator(reader__, #separator) };
-                    } else if let Some(false) = attributes.consume_spaces_between_items {
-                        parse_method = quote! { parse_item_without_consuming_spaces(reader__) };
-                    }
+        output.get_location = quote! {
+            fn location(&self) -> &parsable::ItemLocation {
+                #default_location
+            }
+        };
+    }
+
+    let root_marker_check = root_attributes.get_marker_check();
+
+    let declare_recovered = root_attributes.get_recovered_declaration();
+    let make_result = match is_partial {
+        true => quote! { Ok(#make_self) },
+        false => quote! { Some(#make_self) },
+    };
+    let mut parse_body = quote! {
+        let start_index__ = reader__.get_index();
+        let mut field_index__ : usize = 0;
+        let mut field_failed__ = false;
+        let mut prefix_ok__ = true;
+        let mut option_failed__ = false;
+        #declare_recovered
+        #root_markers_on_start
+        #(#lines)*
+        #root_markers_on_exit
+        #set_location
+        #make_result
+    };
+
+    if is_partial {
+        output.parse_partial = Some(parse_body);
+        output.partial_fields = Some(partial_fields);
+        parse_body = quote! {
+            Self::parse_partial(reader__).ok()
+        };
+    }
+
+    let parse_body = root_attributes.get_memoized_parse_item(parse_body, false);
+
+    output.parse_item = quote! {
+        fn parse_item(reader__: &mut parsable::StringReader) -> Option<Self> {
+            #root_marker_check
+            #parse_body
+        }
+    };
+
+    output.write_source = make_write_source(quote! { #(#write_lines)* });
+
+    if root_attributes.impl_display {
+        output.display = Some(make_display(quote! { #(#display_lines)* }));
+    }
+}
+
+// Tuple struct fields are bound to `field_<i>` while parsing
+pub fn get_field_name(field: &Field, i: usize) -> Ident {
+    match &field.ident {
+        Some(ident) => ident.clone(),
+        None => make_ident(format!("field_{}", i)),
+    }
+}
+
+// `parent_name` is the name of the item the field belongs to, `exit_on_fail` resets the reader and returns from the parsing function when the field cannot be parsed
+pub fn process_field(field: &Field, attributes: FieldAttributes, i: usize, root_attributes: &RootAttributes, parent_name: &str, markers_on_fail: &mut Vec<TokenStream>, exit_on_fail: &TokenStream) -> TokenStream {
+    let field_name = &get_field_name(field, i);
+
+    // A `Result<T, parsable::ItemLocation>` field is parsed as a `T`
+    let recovered_type = get_recovered_type(&field.ty);
+    let is_recovered = recovered_type.is_some();
+    let declared_type = &field.ty;
+    let field_type = recovered_type.unwrap_or(declared_type);
+
+    attributes.check_field_type(field_type);
+
+    let is_supported = check_type_support(field_type);
+
+    let (field_markers_on_start, field_markers_on_exit, field_markers_on_fail) = attributes.get_push_pop_markers(i);
+    let is_vec = attributes.is_list(field_type);
+    let is_option = attributes.is_option(field_type);
+    // A `list` type the macro doesn't recognize, such as an alias, is parsed as a `Vec` of its items then collected into it
+    let list_type : Option<Type> = match attributes.list && !is_type(field_type, "Vec") && !is_collection(field_type) {
+        true => Some(syn::parse2(quote! { Vec<<#field_type as IntoIterator>::Item> }).unwrap()),
+        false => None,
+    };
+    let parsed_type = list_type.as_ref().unwrap_or(field_type);
+
+    markers_on_fail.insert(0, field_markers_on_fail.clone());
+
+    let pass_marker_test = attributes.get_marker_test();
+    let optional = is_option || attributes.optional.unwrap_or(false);
+    let participate_in_cascade = root_attributes.cascade && attributes.cascade.unwrap_or(true);
+    let consume_spaces = match attributes.consume_spaces {
+        Some(false) => quote! {},
+        _ => quote! { reader__.eat_spaces(); }
+    };
+    let mut handle_failure = quote! {};
+    let mut on_fail = quote ! {
+        #(#markers_on_fail)*
+        #exit_on_fail
+    };
+
+    if optional {
+        emit_meaningless_attribute_error(&attributes.key_spans, &["sync", "recover_until"], "on an optional field, which cannot fail");
+    }
+
+    // A field with no synchronization token of its own recovers at the one of the item, after which the next fields are skipped
+    let recovers_item = attributes.sync.is_none() && attributes.recover_until.is_none() && root_attributes.has_recovery();
+    let skip_to_sync = match recovers_item {
+        true => get_skip_to_sync(&root_attributes.sync, &root_attributes.recover_until),
+        false => get_skip_to_sync(&attributes.sync, &attributes.recover_until),
+    };
+    let can_recover = root_attributes.has_recovery() || skip_to_sync.is_some();
+    let field_label = field.ident.as_ref().map(|ident| ident.to_string()).unwrap_or_else(|| i.to_string());
+
+    // The error node of a field that failed is the location of the skipped input, the optional fields are left empty instead
+    let failed_value = match is_recovered {
+        true => quote! { Err(reader__.get_item_location(field_index__)) },
+        false => quote! { <#field_type as Default>::default() },
+    };
+
+    let type_name = quote! { #field_type }.to_string();
+
+    match (is_recovered, attributes.ignore) {
+        (true, false) if !can_recover || optional => {
+            emit_error!(declared_type, "only the fields that can fail and recover from it hold an error";
+                help = "add `sync` or `recover_until` to a field that is not optional, or declare it as `{}`", type_name);
+        },
+        (false, false) if can_recover && !optional => {
+            emit_error!(declared_type, "a field that can recover from an error must be able to hold it";
+                help = "declare it as `Result<{}, parsable::ItemLocation>`, which is `Err` with the location of the skipped input when it failed", type_name);
+        },
+        _ => {}
+    }
+
+    if let (Some(skip_to_sync), false) = (skip_to_sync, optional) {
+        let set_recovered = match recovers_item {
+            true => quote! { recovered__ = true; },
+            false => quote! {},
+        };
+        let read_suffix = attributes.suffix.as_ref().map(|suffix| {
+            let suffix_consume_spaces = match attributes.consume_spaces_after_suffix {
+                Some(false) => quote! {},
+                _ => quote! { reader__.eat_spaces(); },
+            };
+
+            quote! {
+                if reader__.read_string(#suffix).is_some() {
+                    #suffix_consume_spaces
                 }
+            }
+        });
+
+        // The error spans from the start of the field to the synchronization point. It is only kept in the tree, so that the errors of an
+        // alternative that is discarded later on are discarded along with it.
+        on_fail = quote! {
+            match #skip_to_sync {
+                true => {
+                    let failed_value__ = #failed_value;
+                    #read_suffix
+                    field_failed__ = true;
+                    #set_recovered
+                    failed_value__
+                },
+                false => {
+                    #on_fail
+                }
+            }
+        };
+    }
+
+    if optional {
+        let set_option_failed = match participate_in_cascade {
+            true => quote! { option_failed__ = true },
+            false => quote! {},
+        };
+
+        on_fail = quote! {
+            field_failed__ = true;
+            #set_option_failed;
+            reader__.set_index(field_index__);
+            <#field_type as Default>::default()
+        };
+
+        if attributes.suffix.is_some() {
+            handle_failure = quote! {
+                if field_failed__ {
+                    #field_name = <#field_type as Default>::default();
+                }
+            }
+        }
+    }
+
+    // A field holding an error becomes the error node when it fails after being parsed, e.g. on its suffix
+    let (fail_before_value, fail_after_value) = match is_recovered {
+        true => (quote! { let _ : #declared_type = #on_fail; }, quote! { #field_name = #on_fail; }),
+        false => (quote! { #on_fail; }, quote! { #on_fail; }),
+    };
+    let wrap_value = |value: TokenStream| match is_recovered {
+        true => quote! { Ok(#value) },
+        false => value,
+    };
+    // The checks made on the parsed value only apply when it is not an error node
+    let check_value = |condition: TokenStream| match is_recovered {
+        true => quote! { matches!(&#field_name, Ok(value__) if #condition) },
+        false => condition,
+    };
+    let value = match is_recovered {
+        true => quote! { value__ },
+        false => quote! { #field_name },
+    };
+
+    let error = &attributes.error;
+    let expect_item = make_expectation(get_expected_item(parsed_type), error, parent_name, &field_label, None);
+    let expect_min = make_expectation(Expected::Item(parsed_type), error, parent_name, &field_label, Some("min"));
+    let expect_string = make_expectation(Expected::Nothing, error, parent_name, &field_label, None);
+    let expect_exclude = make_expectation(Expected::Nothing, error, parent_name, &field_label, Some("exclude"));
+    let mut check = vec![];
+    let has_prefix = attributes.prefix.is_some();
+    let has_suffix = attributes.suffix.is_some();
+
+    let mut pre_parsing_check = quote! {};
+
+    if optional && participate_in_cascade {
+        pre_parsing_check = quote! {
+            if option_failed__ {
+                field_failed__ = true;
+            }
+        };
+    }
+
+    let prefix_parsing = match attributes.prefix {
+        Some(prefix) => {
+            let expect_prefix = make_expectation(Expected::String(prefix.clone()), error, parent_name, &field_label, Some("prefix"));
+            let prefix_consume_spaces = match attributes.consume_spaces_after_prefix {
+                Some(false) => quote! { {} },
+                _ => quote! { reader__.eat_spaces() },
+            };
 
-                let mut assignment = quote! {
-                    let mut #field_name = match <#field_type as parsable::Parsable>::#parse_method {
-                        Some(value) => value,
+            quote! {
+                if !field_failed__ {
+                    match reader__.read_string(#prefix) {
+                        Some(_) => #prefix_consume_spaces,
                         None => {
-                            reader__.set_expected_item::<#field_type>();
-                            #on_fail
+                            #expect_prefix
+                            prefix_ok__ = false;
+                            field_failed__ = true;
+                            #fail_before_value
                         }
                     };
-                };
+                }
+            }
+        },
+        None => quote! {}
+    };
+    let suffix_parsing = match attributes.suffix {
+        Some(suffix) => {
+            let expect_suffix = make_expectation(Expected::String(suffix.clone()), error, parent_name, &field_label, Some("suffix"));
+            let suffix_consume_spaces = match attributes.consume_spaces_after_suffix {
+                Some(false) => quote! { {} },
+                _ => quote! { reader__.eat_spaces() },
+            };
 
-                if (has_prefix || participate_in_cascade) && optional {
-                    assignment = quote! {
-                        let mut #field_name = match prefix_ok__ && !option_failed__ {
-                            true => match <#field_type as parsable::Parsable>::#parse_method {
-                                Some(value) => value,
-                                None => {
-                                    reader__.set_expected_item::<#field_type>();
-                                    #on_fail
-                                }
-                            },
-                            false => <#field_type as Default>::default()
-                        };
+            quote! {
+                if !field_failed__ {
+                    match reader__.read_string(#suffix) {
+                        Some(_) => #suffix_consume_spaces,
+                        None => {
+                            #expect_suffix
+                            #fail_after_value
+                        }
                     };
-
-                    // assignment = quote! {
-                    //     let #field_name = <#field_type as Default>::default();
-                    // };
                 }
+            }
+        },
+        None => quote! {}
+    };
 
-                let make_field_from_string = match is_option {
-                    true => quote! { Some(value) },
-                    false => quote! { value },
-                };
+    let mut exclude_parsing = quote! {};
 
-                if let Some(pattern) = attributes.regex {
-                    assignment = quote! {
-                        let #field_name = match reader__.read_regex(#pattern) {
-                            Some(value) => match <String as std::str::FromStr>::from_str(value) {
-                                Ok(value) => #make_field_from_string,
-                                Err(_) => { #on_fail }
-                            },
-                            None => { #on_fail }
-                        };
-                    };
-                } else if let Some(literal) = attributes.value {
-                    assignment = quote! {
-                        let #field_name = match reader__.read_string(#literal) {
-                            Some(value) => match <String as std::str::FromStr>::from_str(value) {
-                                Ok(value) => #make_field_from_string,
-                                Err(_) => { #on_fail }
-                            },
-                            None => { #on_fail }
-                        };
-                    };
+    if let Some(exclude) = &attributes.exclude {
+        exclude_parsing = quote! {
+            if !field_failed__ && reader__.peek_regex(#exclude) {
+                #expect_exclude
+                #fail_before_value
+            }
+        };
+    }
+
+    let mut followed_by_parsing = quote! {};
+
+    if let Some(followed_by) = &attributes.followed_by {
+        let expect_followed_by = make_expectation(Expected::Regex(followed_by.clone()), error, parent_name, &field_label, Some("followed_by"));
 
-                    if field_count == 1 && root_attributes.token.is_none() {
-                        root_attributes.token = Some(literal.clone());
+        followed_by_parsing = quote! {
+            if !field_failed__ && !reader__.peek_regex(#followed_by) {
+                #expect_followed_by
+                #fail_after_value
+            }
+        };
+    } else if let Some(not_followed_by) = &attributes.not_followed_by {
+        followed_by_parsing = quote! {
+            if !field_failed__ && reader__.peek_regex(#not_followed_by) {
+                // reader__.set_expected_regex(#not_followed_by);
+                #fail_after_value
+            }
+        };
+    }
+
+    let mut parse_method = quote! { parse_item(reader__) };
+
+    if is_vec {
+        if let Some(separator) = attributes.separator {
+            parse_method = quote! { parse_item_with_separator(reader__, #separator) };
+        } else if let Some(false) = attributes.consume_spaces_between_items {
+            parse_method = quote! { parse_item_without_consuming_spaces(reader__) };
+        }
+    }
+
+    let parse_expression = match (is_supported, &list_type) {
+        (true, Some(list_type)) => quote! { <#list_type as parsable::Parsable>::#parse_method.map(|items__| items__.into_iter().collect::<#field_type>()) },
+        (true, None) => get_parse_expression(field_type, &parse_method, &consume_spaces),
+        (false, _) => quote! { Option::<#field_type>::None },
+    };
+    let parsed_value = wrap_value(quote! { value__ });
+    let mut parse_value = quote! {
+        match #parse_expression {
+            Some(value__) => #parsed_value,
+            None => {
+                #expect_item
+                #on_fail
+            }
+        }
+    };
+
+    if (has_prefix || participate_in_cascade) && optional {
+        parse_value = quote! {
+            match prefix_ok__ && !option_failed__ {
+                true => match #parse_expression {
+                    Some(value__) => value__,
+                    None => {
+                        #expect_item
+                        #on_fail
                     }
-                }
+                },
+                false => <#field_type as Default>::default()
+            }
+        };
 
-                if let Some(min) = attributes.min {
-                    check.push(quote! {
-                        if !field_failed__ && #field_name.len() < #min {
-                            reader__.set_expected_item::<#field_type>();
-                            #on_fail;
-                        }
-                    });
+        // assignment = quote! {
+        //     let #field_name = <#field_type as Default>::default();
+        // };
+    }
+
+    let make_field_from_string = wrap_value(match is_option {
+        true => quote! { Some(value__) },
+        false => quote! { value__ },
+    });
+
+    if let Some(pattern) = attributes.regex {
+        parse_value = quote! {
+            match reader__.read_regex(#pattern) {
+                Some(value__) => match <String as std::str::FromStr>::from_str(value__) {
+                    Ok(value__) => #make_field_from_string,
+                    Err(_) => { #on_fail }
+                },
+                None => {
+                    #expect_string
+                    #on_fail
+                }
+            }
+        };
+    } else if let Some(literal) = attributes.value {
+        parse_value = quote! {
+            match reader__.read_string(#literal) {
+                Some(value__) => match <String as std::str::FromStr>::from_str(value__) {
+                    Ok(value__) => #make_field_from_string,
+                    Err(_) => { #on_fail }
+                },
+                None => {
+                    #expect_string
+                    #on_fail
                 }
+            }
+        };
+    }
 
-                if is_option && has_prefix {
-                    check.push(quote! {
-                        if #field_name.is_none() {
-                            #on_fail;
-                        }
-                    });
+    // A field that failed before being parsed (e.g. on its prefix) is not parsed at all once the error has been recovered from
+    let assignment = match can_recover {
+        true => quote! {
+            let mut #field_name = match field_failed__ {
+                true => #failed_value,
+                false => #parse_value
+            };
+        },
+        false => quote! {
+            let mut #field_name = #parse_value;
+        }
+    };
+
+    if let Some(min) = attributes.min {
+        let too_short = check_value(quote! { #value.len() < #min });
+
+        check.push(quote! {
+            if !field_failed__ && #too_short {
+                #expect_min
+                #fail_after_value
+            }
+        });
+    }
+
+    if is_option && has_prefix {
+        let is_none = check_value(quote! { #value.is_none() });
+
+        check.push(quote! {
+            if !field_failed__ && #is_none {
+                #fail_after_value
+            }
+        });
+    }
+
+    if is_vec && has_prefix && !has_suffix {
+        let is_empty = check_value(quote! { #value.is_empty() });
+
+        check.push(quote! {
+            if !field_failed__ && #is_empty && prefix_ok__ {
+                #expect_item
+                #fail_after_value
+            }
+        });
+    }
+
+    let skip_if_recovered = match root_attributes.has_recovery() {
+        true => quote! { field_failed__ = recovered__; },
+        false => quote! {},
+    };
+
+    let parse_field = quote! {
+        field_failed__ = false;
+        #skip_if_recovered
+        prefix_ok__ = true;
+        field_index__ = reader__.get_index();
+        #pre_parsing_check
+        #prefix_parsing
+        #exclude_parsing
+        #assignment
+        #(#check)*
+        #consume_spaces
+        #suffix_parsing
+        #followed_by_parsing
+        #handle_failure
+    };
+
+    if attributes.ignore {
+        quote! {
+            let #field_name = <#declared_type as Default>::default();
+        }
+    } else if let Some(pass_marker_test) = pass_marker_test {
+        let skipped_value = match is_recovered {
+            true => quote! { Err(reader__.get_item_location(reader__.get_index())) },
+            false => quote! { <#field_type as Default>::default() },
+        };
+
+        // The markers are still pushed then restored so that the variables used by `markers_on_fail` always exist
+        quote! {
+            #field_markers_on_start
+            let #field_name = match #pass_marker_test {
+                true => {
+                    #parse_field
+                    #field_name
+                },
+                false => {
+                    #field_markers_on_fail
+                    #skipped_value
                 }
+            };
+            #field_markers_on_exit
+        }
+    } else {
+        quote! {
+            #field_markers_on_start
+            #parse_field
+            #field_markers_on_exit
+        }
+    }
+}
 
-                if is_vec && has_prefix && !has_suffix {
-                    check.push(quote! {
-                        if #field_name.is_empty() && prefix_ok__ {
-                            reader__.set_expected_item::<#field_type>();
-                            #on_fail;
-                        }
-                    });
+// Tuples and arrays are parsed element by element, the other types parse themselves
+pub fn get_parse_expression(ty: &Type, parse_method: &TokenStream, consume_spaces: &TokenStream) -> TokenStream {
+    let parse_element = |element_type: &Type| {
+        let parse_expression = get_parse_expression(element_type, &quote! { parse_item(reader__) }, consume_spaces);
+        let expect_element = match get_expected_item(element_type) {
+            Expected::Item(item_type) => quote! { reader__.set_expected_item::<#item_type>(); },
+            _ => quote! {},
+        };
+
+        quote! {
+            match #parse_expression {
+                Some(value__) => value__,
+                None => {
+                    #expect_element
+                    reader__.set_index(sequence_start__);
+                    return None;
                 }
+            }
+        }
+    };
 
-                if attributes.ignore {
-                    lines.push(quote! {
-                        let #field_name = <#field_type as Default>::default();
-                    });
-                } else {
-                    lines.push(quote! {
-                        #field_markers_on_start
-                        field_failed__ = false;
-                        prefix_ok__ = true;
-                        field_index__ = reader__.get_index();
-                        #pre_parsing_check
-                        #prefix_parsing
-                        #exclude_parsing
-                        #assignment
-                        #(#check)*
-                        #consume_spaces
-                        #suffix_parsing
-                        #followed_by_parsing
-                        #handle_failure
-                        #field_markers_on_exit
-                    });
+    match ty {
+        Type::Tuple(type_tuple) => {
+            let element_names : Vec<Ident> = (0..type_tuple.elems.len()).map(|i| make_ident(format!("element_{}__", i))).collect();
+            let element_lines = type_tuple.elems.iter().zip(&element_names).enumerate().map(|(i, (element_type, element_name))| {
+                let parse_element = parse_element(element_type);
+                let consume_spaces = match i + 1 < element_names.len() {
+                    true => consume_spaces.clone(),
+                    false => quote! {},
+                };
+
+                quote! {
+                    let #element_name = #parse_element;
+                    #consume_spaces
                 }
+            });
+
+            quote! {
+                (|reader__: &mut parsable::StringReader| -> Option<#ty> {
+                    let sequence_start__ = reader__.get_index();
+                    #(#element_lines)*
+                    Some((#(#element_names,)*))
+                })(reader__)
             }
+        },
+        Type::Array(type_array) => {
+            let element_type = &type_array.elem;
+            let length = &type_array.len;
+            let parse_element = parse_element(element_type);
+
+            quote! {
+                (|reader__: &mut parsable::StringReader| -> Option<#ty> {
+                    let sequence_start__ = reader__.get_index();
+                    let mut items__ : Vec<#element_type> = Vec::new();
+
+                    while items__.len() < #length {
+                        if !items__.is_empty() {
+                            #consume_spaces
+                        }
 
-            let mut set_location = quote! {};
+                        items__.push(#parse_element);
+                    }
 
-            if root_attributes.located {
-                field_names.push(quote! { location });
-                named_fields.named.insert(0, create_location_field("location"));
-                set_location = quote! { let location = reader__.get_item_location(start_index__); };
+                    <#ty as std::convert::TryFrom<Vec<#element_type>>>::try_from(items__).ok()
+                })(reader__)
             }
+        },
+        Type::Path(_) if is_type(ty, "Option") && matches!(get_inner_type(ty), Some(Type::Tuple(_) | Type::Array(_))) => {
+            let parse_inner = get_parse_expression(get_inner_type(ty).unwrap(), parse_method, consume_spaces);
 
-            output.parse_item = quote! {
-                fn parse_item(reader__: &mut parsable::StringReader) -> Option<Self> {
-                    let start_index__ = reader__.get_index();
-                    let mut field_index__ : usize = 0;
-                    let mut field_failed__ = false;
-                    let mut prefix_ok__ = true;
-                    let mut option_failed__ = false;
-                    #root_markers_on_start
-                    #(#lines)*
-                    #root_markers_on_exit
-                    #set_location
-                    Some(Self { #(#field_names),* })
-                }
+            quote! { Some(#parse_inner) }
+        },
+        Type::Path(_) if !is_type(ty, "Vec") && is_collection(ty) => {
+            let item_type = get_collection_item_type(ty).unwrap();
+
+            quote! {
+                <Vec<#item_type> as parsable::Parsable>::#parse_method.map(|items__| items__.into_iter().collect::<#ty>())
+            }
+        },
+        Type::Paren(type_paren) => get_parse_expression(&type_paren.elem, parse_method, consume_spaces),
+        Type::Group(type_group) => get_parse_expression(&type_group.elem, parse_method, consume_spaces),
+        _ => quote! { <#ty as parsable::Parsable>::#parse_method }
+    }
+}
+
+// What a field, or one of its attributes, expected when it failed
+pub enum Expected<'a> {
+    Item(&'a Type),
+    String(String),
+    Regex(String),
+    Nothing,
+}
+
+// Tuples and arrays are not items, the element that failed records what it expected
+fn get_expected_item(ty: &Type) -> Expected<'_> {
+    match (ty, get_inner_type(ty)) {
+        (Type::Path(_), _) if !is_type(ty, "Vec") && is_collection(ty) => Expected::Item(get_collection_item_type(ty).unwrap()),
+        (Type::Path(_), Some(Type::Tuple(_) | Type::Array(_))) => Expected::Nothing,
+        (Type::Path(_), _) => Expected::Item(ty),
+        _ => Expected::Nothing,
+    }
+}
+
+// Records what was expected, or the custom `error` message instead. With the `expected-context` feature, the same call also tells the reader
+// which field of which item it comes from, `attribute` being `None` when the field itself failed. Nothing is recorded if there is neither an
+// expectation nor a message.
+pub fn make_expectation(expected: Expected, error: &Option<String>, item_name: &str, field_name: &str, attribute: Option<&str>) -> TokenStream {
+    let (method_name, type_argument, mut arguments) = match (error, expected) {
+        (Some(message), _) if cfg!(feature = "error-messages") => ("set_expected_message", None, vec![quote! { #message }]),
+        (_, Expected::Item(ty)) => ("set_expected_item", Some(quote! { ::<#ty> }), vec![]),
+        (_, Expected::String(string)) => ("set_expected_string", None, vec![quote! { #string }]),
+        (_, Expected::Regex(pattern)) => ("set_expected_regex", None, vec![quote! { #pattern }]),
+        (_, Expected::Nothing) => return quote! {},
+    };
+    let method = match cfg!(feature = "expected-context") {
+        true => {
+            let attribute = match attribute {
+                Some(attribute) => quote! { Some(#attribute) },
+                None => quote! { None },
             };
 
+            arguments.extend(vec![quote! { #item_name }, quote! { #field_name }, attribute]);
+            format_ident!("{}_in", method_name)
         },
-        Fields::Unnamed(_) => unreachable!(),
-        Fields::Unit => unreachable!()
+        false => format_ident!("{}", method_name),
+    };
+
+    quote! {
+        reader__.#method #type_argument (#(#arguments),*);
     }
-}
\ No newline at end of file
+}
+
+// Skips characters until the synchronization token (consumed) or the `recover_until` pattern (not consumed), evaluates to whether it was found
+fn get_skip_to_sync(sync: &Option<String>, recover_until: &Option<String>) -> Option<TokenStream> {
+    let found = match (sync, recover_until) {
+        (Some(sync), _) => quote! { reader__.read_string(#sync).is_some() },
+        (None, Some(pattern)) => quote! { reader__.peek_regex(#pattern) },
+        (None, None) => return None
+    };
+
+    Some(quote! {
+        loop {
+            if #found {
+                break true;
+            }
+
+            if reader__.read_regex("(?s).").is_none() {
+                break false;
+            }
+        }
+    })
+}
@@ -7,10 +7,11 @@ mod impl_struct;
 mod impl_enum;
 mod output;
 mod markers;
+mod source_writer;
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::*;
 use proc_macro_error::*;
 
@@ -27,59 +28,122 @@ pub fn parsable(attr: TokenStream, input: TokenStream) -> TokenStream {
     let mut root_attributes = match syn::parse::<RootAttributes>(attr.clone()) {
         Ok(attributes) => attributes,
         Err(error) => {
-            emit_call_site_error!(error);
+            emit_error!(error);
             RootAttributes::default()
         }
     };
     let mut ast : DeriveInput = syn::parse(input).unwrap();
     let mut output = Output::default();
     let name = &ast.ident;
+    let mut generics = ast.generics.clone();
 
-    let derive_attribute = syn::Attribute {
-        pound_token: Token![#](Span::call_site()),
-        style: AttrStyle::Outer,
-        bracket_token: syn::token::Bracket { span: Span::call_site() },
-        path: syn::parse_str("derive").unwrap(),
-        tokens: syn::parse_str("(Debug)").unwrap(),
-    };
+    for type_param in generics.type_params_mut() {
+        type_param.bounds.push(syn::parse_str("parsable::Parsable").unwrap());
+
+        if root_attributes.left_recursive {
+            type_param.bounds.push(syn::parse_str("Clone").unwrap());
+        }
+
+        if root_attributes.is_memoized() {
+            type_param.bounds.push(syn::parse_str("'static").unwrap());
+        }
+    }
 
-    // if let Data::Enum(_) = ast.data {
-    //     derive_attribute.tokens = syn::parse_str("(Debug, Clone, Copy, PartialEq)").unwrap();
-    // }
+    let mut display_generics = generics.clone();
 
-    ast.attrs.push(derive_attribute);
+    for type_param in display_generics.type_params_mut() {
+        type_param.bounds.push(syn::parse_str("std::fmt::Display").unwrap());
+    }
+
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    let (display_impl_generics, _, display_where_clause) = display_generics.split_for_impl();
+
+    // Only the derives written below `#[parsable]` reach the macro: rustc expands the ones above it first, on the item
+    // without its location field, so they cannot be detected here and conflict with the derives added by the macro
+    let existing_derives = get_existing_derives(&ast.attrs);
+    let derives : Vec<&Path> = root_attributes.derives.iter()
+        .filter(|path| !existing_derives.contains(&path.segments.last().unwrap().ident))
+        .collect();
+
+    if !derives.is_empty() {
+        let derive_attribute = syn::Attribute {
+            pound_token: Token![#](Span::call_site()),
+            style: AttrStyle::Outer,
+            bracket_token: syn::token::Bracket { span: Span::call_site() },
+            path: syn::parse_str("derive").unwrap(),
+            tokens: quote! { (#(#derives),*) },
+        };
+
+        ast.attrs.push(derive_attribute);
+    }
 
     match &mut ast.data {
-        Data::Struct(data) => process_struct(data, &mut root_attributes, &mut output),
-        Data::Enum(data) => process_enum(data, &root_attributes, &mut output),
+        Data::Struct(data) => process_struct(name, data, &mut root_attributes, &mut output),
+        Data::Enum(data) => process_enum(name, data, &root_attributes, &mut output),
         Data::Union(_) => emit_call_site_error!("unions are not supported")
     }
 
     let impl_display = match output.display {
         Some(body) => quote! {
-            impl std::fmt::Display for #name {
+            impl #display_impl_generics std::fmt::Display for #name #type_generics #display_where_clause {
                 #body
             }
         },
         None => quote! {},
     };
 
-    let impl_as_str = match output.as_str {
-        Some(body) => quote! {
-            impl #name {
-                #body
+    let as_str = output.as_str;
+    let parse_with_precedence = output.parse_with_precedence;
+    let partial_name = format_ident!("Partial{}", name);
+    let parse_partial = output.parse_partial.map(|body| quote! {
+        // Like `parse_item`, but gives the fields parsed before the failure instead of nothing
+        pub fn parse_partial(reader__: &mut parsable::StringReader) -> Result<Self, #partial_name #type_generics> {
+            #body
+        }
+    });
+    // `write_source` is only part of the `Parsable` trait in the runtimes that enable the `write-source` feature
+    let (write_source, to_source) = match cfg!(feature = "write-source") {
+        true => (output.write_source, quote! {
+            pub fn to_source(&self) -> String {
+                let mut output = String::new();
+                <Self as parsable::Parsable>::write_source(self, &mut output);
+                output
             }
-        },
-        None => quote! {}
+        }),
+        false => (quote! {}, quote! {}),
     };
+    let impl_inherent = quote! {
+        impl #impl_generics #name #type_generics #where_clause {
+            #as_str
+
+            #parse_with_precedence
 
-    let token_name = match &root_attributes.name {
-        Some(specified_name) => specified_name.to_string(),
-        None => match &root_attributes.token {
-            Some(token) => format!("\"{}\"", token),
-            None => name.to_string(),
+            #parse_partial
+
+            #to_source
         }
     };
+
+    // The partial struct has the same fields as the item, all made optional, and the location of the field that failed
+    let partial_struct = match output.partial_fields {
+        Some(partial_fields) => {
+            let visibility = &ast.vis;
+            let partial_derives = &root_attributes.derives;
+            let (_, _, partial_where_clause) = ast.generics.split_for_impl();
+            let partial_generics = &ast.generics;
+
+            quote! {
+                #[derive(#(#partial_derives),*)]
+                #visibility struct #partial_name #partial_generics #partial_where_clause {
+                    #(#partial_fields,)*
+                    pub failure_location: parsable::ItemLocation,
+                }
+            }
+        },
+        None => quote! {}
+    };
+
+    let token_name = root_attributes.get_item_name(name);
     let token_name_lit = LitStr::new(&token_name, Span::call_site());
 
     let impl_item_name = quote! {
@@ -88,6 +152,37 @@ pub fn parsable(attr: TokenStream, input: TokenStream) -> TokenStream {
         }
     };
 
+    let impl_deref = match &root_attributes.deref_field {
+        Some(field_name) => match get_deref_field(&ast.data, field_name) {
+            Some((member, field_type)) => quote! {
+                impl #impl_generics std::ops::Deref for #name #type_generics #where_clause {
+                    type Target = #field_type;
+
+                    fn deref(&self) -> &#field_type {
+                        &self.#member
+                    }
+                }
+            },
+            None => {
+                emit_error!(field_name, "`{}` has no field `{}`", name, field_name.value());
+                quote! {}
+            }
+        },
+        None => match root_attributes.impl_deref && root_attributes.located {
+            true => quote! {
+                impl #impl_generics std::ops::Deref for #name #type_generics #where_clause {
+                    type Target = parsable::ItemLocation;
+
+                    fn deref(&self) -> &parsable::ItemLocation {
+                        <Self as parsable::Parsable>::location(self)
+                    }
+                }
+            },
+            false => quote! {}
+        }
+    };
+
+    let warnings = output.warnings;
     let get_location = output.get_location;
     let parse_item = output.parse_item;
     let get_completion_suggestions = output.get_completion_suggestions.unwrap_or_default();
@@ -95,9 +190,11 @@ pub fn parsable(attr: TokenStream, input: TokenStream) -> TokenStream {
     let result = quote! {
         #ast
 
-        impl parsable::Parsable for #name {
+        impl #impl_generics parsable::Parsable for #name #type_generics #where_clause {
             #parse_item
 
+            #write_source
+
             #impl_item_name
 
             #get_location
@@ -105,18 +202,49 @@ pub fn parsable(attr: TokenStream, input: TokenStream) -> TokenStream {
             #get_completion_suggestions
         }
 
-        impl std::ops::Deref for #name {
-            type Target = parsable::ItemLocation;
-
-            fn deref(&self) -> &parsable::ItemLocation {
-                <Self as parsable::Parsable>::location(self)
-            }
-        }
+        #impl_deref
 
         #impl_display
 
-        #impl_as_str
+        #impl_inherent
+
+        #partial_struct
+
+        #(#warnings)*
     };
 
     result.into()
+}
+
+fn get_existing_derives(attrs: &[Attribute]) -> Vec<Ident> {
+    let mut derives = vec![];
+
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("derive")) {
+        if let Ok(paths) = attr.parse_args_with(punctuated::Punctuated::<Path, Token![,]>::parse_terminated) {
+            derives.extend(paths.into_iter().map(|path| path.segments.last().unwrap().ident.clone()));
+        }
+    }
+
+    derives
+}
+
+fn get_deref_field(data: &Data, field_name: &LitStr) -> Option<(proc_macro2::TokenStream, Type)> {
+    let fields = match data {
+        Data::Struct(data_struct) => &data_struct.fields,
+        _ => return None
+    };
+    let name = field_name.value();
+
+    fields.iter().enumerate().find_map(|(i, field)| {
+        let member = match &field.ident {
+            Some(ident) if ident == &name => quote! { #ident },
+            None if i.to_string() == name => {
+                let index = Index::from(i);
+                quote! { #index }
+            },
+            _ => return None
+        };
+
+        Some((member, field.ty.clone()))
+    })
 }
\ No newline at end of file
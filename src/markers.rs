@@ -45,11 +45,32 @@ impl MarkerOutput {
         }
     }
 
-    pub fn to_tuple(self) -> (TokenStream, TokenStream, TokenStream) {
+    pub fn into_tuple(self) -> (TokenStream, TokenStream, TokenStream) {
         (
             self.on_parse_start,
             self.on_parse_exit,
             self.on_parse_fail,
         )
     }
+}
+
+pub fn get_marker_test(ignore_if_marker: &[LitStr], ignore_if_not_marker: &[LitStr]) -> Option<TokenStream> {
+    let mut fragments = vec![];
+
+    for marker_name in ignore_if_marker {
+        fragments.push(quote! {
+            !reader__.get_marker(#marker_name)
+        });
+    }
+
+    for marker_name in ignore_if_not_marker {
+        fragments.push(quote! {
+            reader__.get_marker(#marker_name)
+        });
+    }
+
+    match fragments.is_empty() {
+        true => None,
+        false => Some(quote! { #(#fragments)&&* })
+    }
 }
\ No newline at end of file
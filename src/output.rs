@@ -4,8 +4,13 @@ use proc_macro2::TokenStream;
 pub struct Output {
     pub display: Option<TokenStream>,
     pub as_str: Option<TokenStream>,
+    pub parse_with_precedence: Option<TokenStream>,
+    pub parse_partial: Option<TokenStream>,
+    pub partial_fields: Option<Vec<TokenStream>>,
     pub get_location: TokenStream,
     pub parse_item: TokenStream,
+    pub write_source: TokenStream,
     // pub token_name: TokenStream,
-    pub get_completion_suggestions: Option<TokenStream>
+    pub get_completion_suggestions: Option<TokenStream>,
+    pub warnings: Vec<TokenStream>,
 }
\ No newline at end of file
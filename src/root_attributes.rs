@@ -1,34 +1,79 @@
-use proc_macro2::TokenStream;
+use std::collections::HashMap;
+use proc_macro2::{Span, TokenStream};
+use proc_macro_error::emit_error;
+use quote::quote;
 use syn::{*, parse::{Parse, ParseStream}};
-use crate::markers::MarkerOutput;
+use crate::{markers::{MarkerOutput, get_marker_test}, utils::{check_regex, emit_missing_feature_error, emit_unknown_attribute_error, skip_attribute_value}};
+
+const ROOT_ATTRIBUTE_NAMES : &[&str] = &[
+    "located", "located_variants", "impl_display", "cascade", "name", "token", "value", "derive", "no_derive", "deref", "choice", "memoize",
+    "left_recursive", "sync", "recover_until", "partial",
+    "declare_marker", "set_marker", "unset_marker", "ignore_if_marker", "ignore_if_not_marker",
+];
+
+const REPEATABLE_ROOT_ATTRIBUTE_NAMES : &[&str] = &[
+    "declare_marker", "set_marker", "unset_marker", "ignore_if_marker", "ignore_if_not_marker",
+];
+
+// How an enum picks the variant it returns
+#[derive(Clone, Copy, PartialEq)]
+pub enum Choice {
+    First,
+    Longest,
+    Strict,
+}
 
 // TODO: add prefix and suffix
 pub struct RootAttributes {
     pub located: bool,
+    pub located_variants: bool,
     pub impl_display: bool,
     pub cascade: bool,
     pub name: Option<String>,
     pub token: Option<String>,
+    pub value: Option<String>,
     pub declared_markers: Vec<LitStr>,
     pub set_markers: Vec<LitStr>,
     pub unset_markers: Vec<LitStr>,
     pub ignore_if_marker: Vec<LitStr>,
     pub ignore_if_not_marker: Vec<LitStr>,
+    pub derives: Vec<Path>,
+    pub impl_deref: bool,
+    pub deref_field: Option<LitStr>,
+    pub choice: Choice,
+    pub memoize: bool,
+    pub left_recursive: bool,
+    pub sync: Option<String>,
+    pub recover_until: Option<String>,
+    pub partial: bool,
+    pub key_spans: HashMap<String, Span>,
 }
 
 impl Default for RootAttributes {
     fn default() -> Self {
         Self {
             located: true,
+            located_variants: false,
             impl_display: false,
             cascade: false,
             name: None,
             token: None,
+            value: None,
             declared_markers: vec![],
             set_markers: vec![],
             unset_markers: vec![],
             ignore_if_marker: vec![],
             ignore_if_not_marker: vec![],
+            derives: vec![syn::parse_str("Debug").unwrap()],
+            impl_deref: true,
+            deref_field: None,
+            choice: Choice::First,
+            memoize: false,
+            left_recursive: false,
+            sync: None,
+            recover_until: None,
+            partial: false,
+            key_spans: HashMap::new(),
         }
     }
 }
@@ -38,20 +83,75 @@ impl Parse for RootAttributes {
         let mut attributes = RootAttributes::default();
 
         while !content.is_empty() {
-            let name = content.parse::<Ident>()?.to_string();
-            content.parse::<Token![=]>()?;
-
-            match name.as_str() {
-                "located" => attributes.located = content.parse::<LitBool>()?.value(),
-                "impl_display" => attributes.impl_display = content.parse::<LitBool>()?.value(),
-                "cascade" => attributes.cascade = content.parse::<LitBool>()?.value(),
-                "name" => attributes.name = Some(content.parse::<LitStr>()?.value()),
-                "declare_marker" => attributes.declared_markers.push(content.parse::<LitStr>()?),
-                "set_marker" => attributes.set_markers.push(content.parse::<LitStr>()?),
-                "unset_marker" => attributes.unset_markers.push(content.parse::<LitStr>()?),
-                "ignore_if_marker" => attributes.ignore_if_marker.push(content.parse::<LitStr>()?),
-                "ignore_if_not_marker" => attributes.ignore_if_not_marker.push(content.parse::<LitStr>()?),
-                _ => {}
+            let ident = content.parse::<Ident>()?;
+            let name = ident.to_string();
+
+            if !ROOT_ATTRIBUTE_NAMES.contains(&name.as_str()) {
+                emit_unknown_attribute_error(&ident, ROOT_ATTRIBUTE_NAMES);
+                skip_attribute_value(content)?;
+            } else {
+                if attributes.key_spans.contains_key(&name) && !REPEATABLE_ROOT_ATTRIBUTE_NAMES.contains(&name.as_str()) {
+                    emit_error!(ident, "duplicate attribute `{}`", name);
+                }
+
+                attributes.key_spans.insert(name.clone(), ident.span());
+
+                if name == "no_derive" {
+                    attributes.derives.clear();
+                } else if name == "derive" {
+                    let derives;
+
+                    parenthesized!(derives in content);
+                    attributes.derives = derives.parse_terminated::<Path, Token![,]>(Path::parse_mod_style)?.into_iter().collect();
+                } else {
+                    content.parse::<Token![=]>()?;
+
+                    match name.as_str() {
+                        "located" => attributes.located = content.parse::<LitBool>()?.value(),
+                        "located_variants" => attributes.located_variants = content.parse::<LitBool>()?.value(),
+                        "impl_display" => attributes.impl_display = content.parse::<LitBool>()?.value(),
+                        "cascade" => attributes.cascade = content.parse::<LitBool>()?.value(),
+                        "name" => attributes.name = Some(content.parse::<LitStr>()?.value()),
+                        "token" => attributes.token = Some(content.parse::<LitStr>()?.value()),
+                        "value" => attributes.value = Some(content.parse::<LitStr>()?.value()),
+                        "deref" => match content.peek(LitStr) {
+                            true => attributes.deref_field = Some(content.parse::<LitStr>()?),
+                            false => attributes.impl_deref = content.parse::<LitBool>()?.value(),
+                        },
+                        "choice" => {
+                            let lit_str = content.parse::<LitStr>()?;
+
+                            attributes.choice = match lit_str.value().as_str() {
+                                "first" => Choice::First,
+                                "longest" => Choice::Longest,
+                                "strict" => Choice::Strict,
+                                other => {
+                                    emit_error!(lit_str, "unknown choice `{}`", other; help = "expected \"first\", \"longest\" or \"strict\"");
+                                    Choice::First
+                                }
+                            };
+                        },
+                        "memoize" => attributes.memoize = content.parse::<LitBool>()?.value(),
+                        "left_recursive" => attributes.left_recursive = content.parse::<LitBool>()?.value(),
+                        "partial" => attributes.partial = content.parse::<LitBool>()?.value(),
+                        "sync" => attributes.sync = Some(content.parse::<LitStr>()?.value()),
+                        "recover_until" => {
+                            let lit_str = content.parse::<LitStr>()?;
+
+                            if let Err(message) = check_regex(&lit_str.value()) {
+                                emit_error!(lit_str, "invalid regex: {}", message);
+                            }
+
+                            attributes.recover_until = Some(lit_str.value());
+                        },
+                        "declare_marker" => attributes.declared_markers.push(content.parse::<LitStr>()?),
+                        "set_marker" => attributes.set_markers.push(content.parse::<LitStr>()?),
+                        "unset_marker" => attributes.unset_markers.push(content.parse::<LitStr>()?),
+                        "ignore_if_marker" => attributes.ignore_if_marker.push(content.parse::<LitStr>()?),
+                        "ignore_if_not_marker" => attributes.ignore_if_not_marker.push(content.parse::<LitStr>()?),
+                        _ => unreachable!()
+                    }
+                }
             }
 
             if !content.is_empty() {
@@ -59,12 +159,151 @@ impl Parse for RootAttributes {
             }
         }
 
+        if let (Some(_), Some(span)) = (attributes.key_spans.get("derive"), attributes.key_spans.get("no_derive")) {
+            emit_error!(*span, "`no_derive` cannot be used along with `derive`");
+        }
+
+        if let (Some(_), Some(span)) = (attributes.key_spans.get("token"), attributes.key_spans.get("value")) {
+            emit_error!(*span, "`value` cannot be used along with `token`");
+        }
+
+        if let (Some(_), Some(span)) = (attributes.key_spans.get("sync"), attributes.key_spans.get("recover_until")) {
+            emit_error!(*span, "`recover_until` cannot be used along with `sync`");
+        }
+
+        if attributes.choice == Choice::Strict && !cfg!(feature = "ambiguity-detection") {
+            emit_missing_feature_error(&attributes.key_spans, &["choice"], "ambiguity-detection", "`StringReader::add_ambiguity`");
+        }
+
+        if !cfg!(feature = "memoization") {
+            emit_missing_feature_error(&attributes.key_spans, &["memoize"], "memoization", "`StringReader::get_marker_state`, `get_memoized_choice` and `memoize_choice`");
+            emit_missing_feature_error(&attributes.key_spans, &["left_recursive"], "memoization", "`StringReader::get_marker_state`, `get_memoized_item` and `memoize_item`");
+        }
+
+        // Left-recursive items are grown from a seed that is cloned out of the reader
+        let derives_clone = attributes.derives.iter().any(|path| path.is_ident("Clone"));
+
+        if attributes.left_recursive && !attributes.key_spans.contains_key("no_derive") && !derives_clone {
+            attributes.derives.push(syn::parse_str("Clone").unwrap());
+        }
+
         Ok(attributes)
     }
 }
 
 impl RootAttributes {
+    // Makes `parse_item` fail right away when the marker conditions are not met
+    pub fn get_marker_check(&self) -> TokenStream {
+        match get_marker_test(&self.ignore_if_marker, &self.ignore_if_not_marker) {
+            Some(pass_marker_test) => quote! {
+                if !(#pass_marker_test) {
+                    return None;
+                }
+            },
+            None => quote! {}
+        }
+    }
+
+    // Name used in the expectations and diagnostics about the item
+    pub fn get_item_name(&self, name: &Ident) -> String {
+        match &self.name {
+            Some(specified_name) => specified_name.to_string(),
+            None => match &self.token {
+                Some(token) => format!("\"{}\"", token),
+                None => name.to_string(),
+            }
+        }
+    }
+
+    // Unit variants and variants without exactly one field only get a location field on demand, as it changes their shape, they report the empty location otherwise
+    pub fn stores_variant_locations(&self) -> bool {
+        self.located && self.located_variants
+    }
+
+    pub fn is_memoized(&self) -> bool {
+        self.memoize || self.left_recursive
+    }
+
+    // Caches the outcome of `parse_item` in the reader, keyed by the index and the state of the markers. Only failures and the
+    // variant that matched are cached: a failure is then free, and a success only parses the variant that matched the first
+    // time, whose nested items are cached in turn. Left-recursive items cache their value instead, as it is the seed to grow.
+    // With `has_choices`, `body` only tries the variant `choice__` when it is set, and sets `chosen__` to the one that matched.
+    pub fn get_memoized_parse_item(&self, body: TokenStream, has_choices: bool) -> TokenStream {
+        if !self.is_memoized() {
+            return body;
+        }
+
+        if !self.left_recursive {
+            let (declare_chosen, chosen) = match has_choices {
+                true => (quote! { let mut chosen__ : usize = 0; }, quote! { chosen__ }),
+                false => (quote! {}, quote! { 0 }),
+            };
+
+            return quote! {
+                let memo_index__ = reader__.get_index();
+                let memo_markers__ = reader__.get_marker_state();
+                let choice__ = match reader__.get_memoized_choice::<Self>(memo_index__, memo_markers__) {
+                    Some(None) => return None,
+                    Some(Some(choice__)) => Some(choice__),
+                    None => None,
+                };
+                #declare_chosen
+                let result__ = (|reader__: &mut parsable::StringReader| -> Option<Self> { #body })(reader__);
+
+                if choice__.is_none() {
+                    reader__.memoize_choice::<Self>(memo_index__, memo_markers__, result__.as_ref().map(|_| #chosen));
+                }
+
+                result__
+            };
+        }
+
+        // A failure is cached first, then the item is parsed again as long as the cached result lets it go further
+        quote! {
+            let memo_index__ = reader__.get_index();
+            let memo_markers__ = reader__.get_marker_state();
+
+            if let Some((result__, end_index__)) = reader__.get_memoized_item::<Self>(memo_index__, memo_markers__) {
+                reader__.set_index(end_index__);
+                return result__;
+            }
+
+            let mut result__ : Option<Self> = None;
+            let mut end_index__ = memo_index__;
+
+            reader__.memoize_item::<Self>(memo_index__, memo_markers__, None, memo_index__);
+
+            loop {
+                reader__.set_index(memo_index__);
+
+                match (|reader__: &mut parsable::StringReader| -> Option<Self> { #body })(reader__) {
+                    Some(value__) if result__.is_none() || reader__.get_index() > end_index__ => {
+                        end_index__ = reader__.get_index();
+                        reader__.memoize_item::<Self>(memo_index__, memo_markers__, Some(value__.clone()), end_index__);
+                        result__ = Some(value__);
+                    },
+                    _ => break
+                }
+            }
+
+            reader__.set_index(end_index__);
+            result__
+        }
+    }
+
+    pub fn has_recovery(&self) -> bool {
+        self.sync.is_some() || self.recover_until.is_some()
+    }
+
+    // Set once a field has recovered at the synchronization token of the item, the remaining fields are then skipped as if they had failed
+    pub fn get_recovered_declaration(&self) -> TokenStream {
+        match self.has_recovery() {
+            true => quote! { let mut recovered__ = false; },
+            false => quote! {},
+        }
+    }
+
     pub fn get_push_pop_markers(&self) -> (TokenStream, TokenStream, TokenStream) {
-        MarkerOutput::from_attributes(&self.declared_markers, &self.set_markers, &self.unset_markers, None).to_tuple()
+        MarkerOutput::from_attributes(&self.declared_markers, &self.set_markers, &self.unset_markers, None).into_tuple()
     }
-}
\ No newline at end of file
+}
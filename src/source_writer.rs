@@ -0,0 +1,234 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Index, Type, TypeInfer};
+use crate::{field_attributes::FieldAttributes, utils::{get_collection_item_type, get_inner_type, get_recovered_type, is_option_of, is_type, make_ident}};
+
+pub enum FieldKind {
+    Item,
+    String,
+    Option(Type),
+    OptionString,
+    Vec(Type),
+}
+
+// Describes how a field is printed back, mirroring the way it is parsed
+pub struct FieldWriter {
+    pub kind: FieldKind,
+    pub ty: Type,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    pub separator: Option<String>,
+    pub space_after_prefix: bool,
+    pub space_after_item: bool,
+    pub space_after_suffix: bool,
+    pub space_between_items: bool,
+    pub skip_if_empty: bool,
+    pub ignore: bool,
+    pub recovered: bool,
+}
+
+impl FieldWriter {
+    pub fn from_attributes(attributes: &FieldAttributes, ty: &Type) -> Self {
+        // A field that can hold an error is written like its value, and not at all when it failed
+        let recovered_type = get_recovered_type(ty);
+        let ty = recovered_type.unwrap_or(ty);
+        let is_string_literal = attributes.regex.is_some() || attributes.value.is_some();
+        // The type of the items is inferred when it is hidden behind an alias
+        let infer_type = || Type::Infer(TypeInfer { underscore_token: Default::default() });
+        let kind = match (get_inner_type(ty), is_string_literal) {
+            (Some(_), true) if is_option_of(ty, "String") => FieldKind::OptionString,
+            (_, true) => FieldKind::String,
+            (Some(inner_type), false) if is_type(ty, "Option") => FieldKind::Option(inner_type.clone()),
+            (_, false) if attributes.option => FieldKind::Option(infer_type()),
+            (_, false) if attributes.is_list(ty) => FieldKind::Vec(get_collection_item_type(ty).cloned().unwrap_or_else(infer_type)),
+            _ => FieldKind::Item,
+        };
+
+        Self {
+            kind,
+            ty: ty.clone(),
+            prefix: attributes.prefix.clone(),
+            suffix: attributes.suffix.clone(),
+            separator: attributes.separator.clone(),
+            space_after_prefix: attributes.consume_spaces_after_prefix.unwrap_or(true),
+            space_after_item: attributes.consume_spaces.unwrap_or(true),
+            space_after_suffix: attributes.consume_spaces_after_suffix.unwrap_or(true),
+            space_between_items: attributes.consume_spaces_between_items.unwrap_or(true),
+            skip_if_empty: attributes.optional.unwrap_or(false) || attributes.suffix.is_none(),
+            ignore: attributes.ignore,
+            recovered: recovered_type.is_some(),
+        }
+    }
+
+    // `value` is an expression evaluating to a reference to the field, `write_item` generates the code writing a single nested item
+    pub fn to_tokens(&self, value: &TokenStream, write_item: &dyn Fn(&TokenStream, &Type) -> TokenStream) -> TokenStream {
+        if self.ignore {
+            return quote! {};
+        }
+
+        if self.recovered {
+            let content = self.write_field(&quote! { value__ }, write_item);
+
+            return quote! {
+                if let Ok(value__) = #value {
+                    #content
+                }
+            };
+        }
+
+        self.write_field(value, write_item)
+    }
+
+    fn write_field(&self, value: &TokenStream, write_item: &dyn Fn(&TokenStream, &Type) -> TokenStream) -> TokenStream {
+        let item = quote! { item__ };
+        let prefix = self.prefix.as_ref().map(|prefix| write_component(quote! { output__.push_str(#prefix); }, self.space_after_prefix));
+        let suffix = self.suffix.as_ref().map(|suffix| write_component(quote! { output__.push_str(#suffix); }, self.space_after_suffix));
+
+        match &self.kind {
+            FieldKind::Item => {
+                let content = self.write_value(value, &self.ty, write_item, 0);
+
+                quote! { #prefix #content #suffix }
+            },
+            FieldKind::String => {
+                let content = write_component(quote! { output__.push_str(#value); }, self.space_after_item);
+
+                quote! { #prefix #content #suffix }
+            },
+            FieldKind::Option(inner_type) => {
+                let content = self.write_value(&item, inner_type, write_item, 0);
+
+                quote! {
+                    if let Some(item__) = #value {
+                        #prefix #content #suffix
+                    }
+                }
+            },
+            FieldKind::OptionString => {
+                let content = write_component(quote! { output__.push_str(item__); }, self.space_after_item);
+
+                quote! {
+                    if let Some(item__) = #value {
+                        #prefix #content #suffix
+                    }
+                }
+            },
+            FieldKind::Vec(inner_type) => {
+                let item_content = self.write_value(&item, inner_type, write_item, 0);
+                let write_separator = match &self.separator {
+                    Some(separator) => quote! {
+                        output__.push_str(#separator);
+                        output__.push(' ');
+                    },
+                    None => match self.space_between_items {
+                        true => quote! { output__.push(' '); },
+                        false => quote! {},
+                    }
+                };
+                let items = write_component(quote! {
+                    for (i__, item__) in (#value).iter().enumerate() {
+                        if i__ > 0 {
+                            #write_separator
+                        }
+
+                        space__ = false;
+                        #item_content
+                    }
+                }, self.space_after_item);
+                let skip_if_empty = self.skip_if_empty;
+
+                quote! {
+                    if !(#skip_if_empty && (#value).is_empty()) {
+                        #prefix #items #suffix
+                    }
+                }
+            },
+        }
+    }
+
+    // Tuples and arrays are written element by element, each element being a separate component
+    fn write_value(&self, value: &TokenStream, ty: &Type, write_item: &dyn Fn(&TokenStream, &Type) -> TokenStream, depth: usize) -> TokenStream {
+        match ty {
+            Type::Tuple(type_tuple) => {
+                let elements = type_tuple.elems.iter().enumerate().map(|(i, element_type)| {
+                    let index = Index::from(i);
+
+                    self.write_value(&quote! { &(#value).#index }, element_type, write_item, depth)
+                });
+
+                quote! { #(#elements)* }
+            },
+            Type::Array(type_array) => {
+                let element = make_ident(format!("element_{}__", depth));
+                let content = self.write_value(&quote! { #element }, &type_array.elem, write_item, depth + 1);
+
+                quote! {
+                    for #element in (#value).iter() {
+                        #content
+                    }
+                }
+            },
+            Type::Paren(type_paren) => self.write_value(value, &type_paren.elem, write_item, depth),
+            Type::Group(type_group) => self.write_value(value, &type_group.elem, write_item, depth),
+            Type::Path(_) | Type::Infer(_) => write_component(write_item(value, ty), self.space_after_item),
+            _ => quote! {},
+        }
+    }
+}
+
+// Writes a piece of source, separated from the previous one by a space if the parser would have consumed spaces between them
+pub fn write_component(content: TokenStream, space_after: bool) -> TokenStream {
+    quote! {
+        {
+            let mark__ = output__.len();
+
+            if space__ {
+                output__.push(' ');
+            }
+
+            let content_start__ = output__.len();
+
+            #content
+
+            if output__.len() == content_start__ {
+                output__.truncate(mark__);
+            } else {
+                space__ = #space_after;
+            }
+        }
+    }
+}
+
+pub fn write_item_source(value: &TokenStream, ty: &Type) -> TokenStream {
+    quote! {
+        <#ty as parsable::Parsable>::write_source(#value, output__);
+    }
+}
+
+pub fn write_item_display(value: &TokenStream, _ty: &Type) -> TokenStream {
+    quote! {
+        output__.push_str(&std::string::ToString::to_string(#value));
+    }
+}
+
+pub fn make_write_source(body: TokenStream) -> TokenStream {
+    quote! {
+        #[allow(unused_mut, unused_variables, unused_assignments)]
+        fn write_source(&self, output__: &mut String) {
+            let mut space__ = false;
+            #body
+        }
+    }
+}
+
+pub fn make_display(body: TokenStream) -> TokenStream {
+    quote! {
+        #[allow(unused_mut, unused_variables, unused_assignments)]
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let output__ = &mut String::new();
+            let mut space__ = false;
+            #body
+            f.write_str(output__)
+        }
+    }
+}
@@ -1,17 +1,291 @@
-use proc_macro2::Span;
-use syn::{Type, Ident};
+use std::collections::HashMap;
+use proc_macro2::{Span, TokenStream, TokenTree};
+use proc_macro_error::emit_error;
+use quote::{quote, quote_spanned};
+use syn::{GenericArgument, Ident, PathArguments, Token, Type, parse::ParseStream};
 
 pub fn is_type(ty: &Type, name: &str) -> bool {
     get_type_name(ty) == name
 }
 
+pub fn is_option_of(ty: &Type, name: &str) -> bool {
+    match get_inner_type(ty) {
+        Some(inner_type) => is_type(ty, "Option") && is_type(inner_type, name),
+        None => false
+    }
+}
+
+pub fn get_inner_type(ty: &Type) -> Option<&Type> {
+    if let Type::Path(type_path) = ty {
+        if let PathArguments::AngleBracketed(arguments) = &type_path.path.segments.last().unwrap().arguments {
+            if let Some(GenericArgument::Type(inner_type)) = arguments.args.first() {
+                return Some(inner_type);
+            }
+        }
+    }
+
+    None
+}
+
+// Whether the type is `Box<Self>` or `Box<name>`, i.e. an item parsing itself
+pub fn is_boxed_self(ty: &Type, name: &Ident) -> bool {
+    match get_inner_type(ty) {
+        Some(inner_type) => is_type(ty, "Box") && (is_type(inner_type, "Self") || is_type(inner_type, &name.to_string())),
+        None => false
+    }
+}
+
+// Collections that are parsed like a `Vec` (the ones that are not a `Vec` are collected from one), returns the type of their items
+pub fn get_collection_item_type(ty: &Type) -> Option<&Type> {
+    let inner_type = get_inner_type(ty)?;
+
+    match (get_type_name(ty).as_str(), inner_type) {
+        ("Vec" | "VecDeque", _) => Some(inner_type),
+        ("SmallVec", Type::Array(type_array)) => Some(&type_array.elem),
+        ("Box", Type::Slice(type_slice)) => Some(&type_slice.elem),
+        _ => None
+    }
+}
+
+pub fn is_collection(ty: &Type) -> bool {
+    get_collection_item_type(ty).is_some()
+}
+
+// `Result<T, parsable::ItemLocation>` fields hold either the parsed value or the location of the input skipped to recover from an error
+pub fn get_recovered_type(ty: &Type) -> Option<&Type> {
+    if let Type::Path(type_path) = ty {
+        if let PathArguments::AngleBracketed(arguments) = &type_path.path.segments.last().unwrap().arguments {
+            if let (true, Some(GenericArgument::Type(value_type)), Some(GenericArgument::Type(error_type))) = (is_type(ty, "Result"), arguments.args.first(), arguments.args.iter().nth(1)) {
+                if is_type(error_type, "ItemLocation") {
+                    return Some(value_type);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// Tuples, arrays and the collections that are not a `Vec` do not implement `Parsable`, so they have no location
+pub fn is_located_type(ty: &Type) -> bool {
+    matches!(ty, Type::Path(_)) && (is_type(ty, "Vec") || !is_collection(ty))
+}
+
+// Types that are not paths (tuples, arrays...) have no name
 fn get_type_name(ty: &Type) -> String {
     match ty {
         Type::Path(type_path) => type_path.path.segments.last().unwrap().ident.to_string(),
-        _ => todo!(),
+        _ => String::new(),
+    }
+}
+
+// A field type that cannot be parsed, along with what to use instead
+struct UnsupportedType<'a> {
+    ty: &'a Type,
+    message: String,
+    help: &'static str,
+}
+
+// Fields can be items, tuples of fields or fixed-size arrays of fields, the other types are reported
+pub fn check_type_support(ty: &Type) -> bool {
+    let unsupported_types = get_unsupported_types(ty);
+
+    for unsupported_type in &unsupported_types {
+        emit_error!(unsupported_type.ty, "{}", unsupported_type.message; help = "{}", unsupported_type.help);
+    }
+
+    unsupported_types.is_empty()
+}
+
+fn get_unsupported_types(ty: &Type) -> Vec<UnsupportedType<'_>> {
+    let unsupported = |ty, message: String, help| vec![UnsupportedType { ty, message, help }];
+
+    match ty {
+        // The items of a collection are parsed as items too, e.g. `SmallVec<[T; N]>` holds `T` items and not arrays
+        Type::Path(_) => match get_collection_item_type(ty).or_else(|| get_inner_type(ty)) {
+            Some(inner_type @ (Type::Tuple(_) | Type::Array(_))) if !is_type(ty, "Option") => {
+                unsupported(inner_type, format!("tuples and arrays are not supported inside `{}`", get_type_name(ty)), "use a struct with `#[parsable]` instead")
+            },
+            Some(inner_type) if get_recovered_type(inner_type).is_some() => {
+                unsupported(inner_type, format!("`Result` is not supported inside `{}`", get_type_name(ty)), "only a field itself can be a `Result<T, parsable::ItemLocation>`")
+            },
+            Some(inner_type) => get_unsupported_types(inner_type),
+            None => vec![]
+        },
+        // Every element is checked so that all the unsupported ones are reported
+        Type::Tuple(type_tuple) => type_tuple.elems.iter().flat_map(get_unsupported_types).collect(),
+        Type::Array(type_array) => get_unsupported_types(&type_array.elem),
+        Type::Paren(type_paren) => get_unsupported_types(&type_paren.elem),
+        Type::Group(type_group) => get_unsupported_types(&type_group.elem),
+        Type::Reference(_) => {
+            unsupported(ty, "reference fields are not supported".to_string(), "use an owned type instead, e.g. `String` with `value` or `regex` instead of `&str`")
+        },
+        Type::Slice(_) => unsupported(ty, "slice fields are not supported".to_string(), "use `Vec<T>`, or `[T; N]` to parse exactly N items"),
+        _ => unsupported(ty, "this field type is not supported".to_string(), "fields must be items, `Option`, `Vec`, tuples or fixed-size arrays"),
+    }
+}
+
+// Patterns are parsed the same way `regex::Regex::new` does at runtime, without compiling them
+pub fn check_regex(pattern: &str) -> Result<(), String> {
+    match regex_syntax::Parser::new().parse(pattern) {
+        Ok(_) => Ok(()),
+        Err(regex_syntax::Error::Parse(error)) => Err(format!("{} (at position {})", error.kind(), error.span().start.offset)),
+        Err(regex_syntax::Error::Translate(error)) => Err(format!("{} (at position {})", error.kind(), error.span().start.offset)),
+        Err(error) => Err(error.to_string()),
     }
 }
 
 pub fn make_ident(name: String) -> Ident {
     Ident::new(&name, Span::call_site())
-}
\ No newline at end of file
+}
+
+pub fn emit_unknown_attribute_error(ident: &Ident, known_names: &[&str]) {
+    let name = ident.to_string();
+
+    match get_closest_name(&name, known_names) {
+        Some(closest) => emit_error!(ident, "unknown attribute `{}`", name; help = "did you mean `{}`?", closest),
+        None => emit_error!(ident, "unknown attribute `{}`", name; help = "expected one of: {}", known_names.join(", ")),
+    }
+}
+
+pub fn emit_meaningless_attribute_error(key_spans: &HashMap<String, Span>, names: &[&str], reason: &str) {
+    for name in names {
+        if let Some(span) = key_spans.get(*name) {
+            emit_error!(*span, "`{}` has no effect {}", name, reason);
+        }
+    }
+}
+
+// The code generated for these attributes calls runtime methods that the published `parsable` crate doesn't have, the runtimes that
+// provide them enable the matching feature
+pub fn emit_missing_feature_error(key_spans: &HashMap<String, Span>, names: &[&str], feature: &str, methods: &str) {
+    for name in names {
+        if let Some(span) = key_spans.get(*name) {
+            emit_error!(*span, "`{}` requires the `{}` feature of `parsable-macro`", name, feature; help = "enable it along with a `parsable` runtime providing {}", methods);
+        }
+    }
+}
+
+// `proc_macro_error` drops warnings on stable, so they are reported through the `deprecated` lint instead
+pub fn make_warning(span: Span, message: String) -> TokenStream {
+    let warning = quote_spanned! { span => parsable_warning };
+
+    quote! {
+        const _: () = {
+            #[deprecated(note = #message)]
+            #[allow(non_camel_case_types)]
+            struct parsable_warning;
+            let _ = #warning;
+        };
+    }
+}
+
+// Skips everything up to the next `,` so that parsing can resume after an invalid attribute
+pub fn skip_attribute_value(content: ParseStream) -> syn::Result<()> {
+    while !content.is_empty() && !content.peek(Token![,]) {
+        content.parse::<TokenTree>()?;
+    }
+
+    Ok(())
+}
+
+fn get_closest_name<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (name.len() / 3).max(1);
+
+    candidates.iter()
+        .map(|candidate| (get_edit_distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b_chars : Vec<char> = b.chars().collect();
+    let mut previous_row : Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current_row = vec![i + 1];
+
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let substitution_cost = if a_char == *b_char { 0 } else { 1 };
+            let distance = (previous_row[j] + substitution_cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+
+            current_row.push(distance);
+        }
+
+        previous_row = current_row;
+    }
+
+    previous_row[b_chars.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_type(string: &str) -> Type {
+        syn::parse_str(string).unwrap()
+    }
+
+    fn get_unsupported_messages(string: &str) -> Vec<String> {
+        get_unsupported_types(&parse_type(string)).into_iter().map(|unsupported_type| unsupported_type.message).collect()
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        let names = &["prefix", "suffix", "separator", "sep", "optional"];
+
+        assert_eq!(get_closest_name("prefx", names), Some("prefix"));
+        assert_eq!(get_closest_name("sufix", names), Some("suffix"));
+        assert_eq!(get_closest_name("seperator", names), Some("separator"));
+        assert_eq!(get_closest_name("optinal", names), Some("optional"));
+        assert_eq!(get_closest_name("color", names), None);
+    }
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(get_edit_distance("", ""), 0);
+        assert_eq!(get_edit_distance("sep", "sep"), 0);
+        assert_eq!(get_edit_distance("", "abc"), 3);
+        assert_eq!(get_edit_distance("kitten", "sitting"), 3);
+        assert_eq!(get_edit_distance("prefix", "prefx"), 1);
+    }
+
+    #[test]
+    fn accepts_valid_regexes() {
+        for pattern in &["[0-9]+", r"\w+", "(?s).", r"\)", "(?i)let|var", r"[^\s;]+"] {
+            assert_eq!(check_regex(pattern), Ok(()), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_regexes() {
+        for pattern in &["(", "[a-", "a{2,1}", r"\", "(?<name", r"\p{Unknown}"] {
+            assert!(check_regex(pattern).is_err(), "{}", pattern);
+        }
+
+        assert!(check_regex("(ab").unwrap_err().contains("(at position 0)"));
+    }
+
+    #[test]
+    fn accepts_supported_types() {
+        for string in &["Num", "Box<Expr>", "Option<Num>", "Vec<Num>", "VecDeque<Num>", "SmallVec<[Num; 4]>", "Box<[Num]>", "(Num, Op)", "[Num; 3]", "Option<(Num, Op)>", "Option<Box<Expr>>", "Result<Num, ItemLocation>"] {
+            assert!(get_unsupported_messages(string).is_empty(), "{}", string);
+        }
+    }
+
+    #[test]
+    fn reports_unsupported_types() {
+        assert_eq!(get_unsupported_messages("&'static str"), vec!["reference fields are not supported"]);
+        assert_eq!(get_unsupported_messages("[Num]"), vec!["slice fields are not supported"]);
+        assert_eq!(get_unsupported_messages("fn()"), vec!["this field type is not supported"]);
+        assert_eq!(get_unsupported_messages("Vec<(Num, Op)>"), vec!["tuples and arrays are not supported inside `Vec`"]);
+        assert_eq!(get_unsupported_messages("Option<Result<Num, ItemLocation>>"), vec!["`Result` is not supported inside `Option`"]);
+        assert_eq!(get_unsupported_messages("Option<&'static str>"), vec!["reference fields are not supported"]);
+        assert_eq!(get_unsupported_messages("Vec<&str>"), vec!["reference fields are not supported"]);
+        assert_eq!(get_unsupported_messages("Box<Option<[Num]>>"), vec!["slice fields are not supported"]);
+        assert_eq!(get_unsupported_messages("(&str, [Num], Num)").len(), 2);
+    }
+}